@@ -0,0 +1,62 @@
+/// largest prime smaller than 2^16
+static MOD_ADLER: u32 = 65521;
+
+/// bytes that can be summed before `b` could overflow a u32
+static NMAX: usize = 5552;
+
+/// Adler-32 as used by the VCD_ADLER32 window checksum of xdelta3
+#[derive(Debug, Clone)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    pub fn new() -> Adler32 {
+        Adler32 { a: 1, b: 0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for chunk in data.chunks(NMAX) {
+            for &byte in chunk {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+            self.a %= MOD_ADLER;
+            self.b %= MOD_ADLER;
+        }
+    }
+
+    pub fn checksum(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Adler32 {
+        Adler32::new()
+    }
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut adler = Adler32::new();
+    adler.update(data);
+    adler.checksum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{adler32, Adler32};
+
+    #[test]
+    fn known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+
+        let data = vec![0xFFu8; 100_000];
+        let mut adler = Adler32::new();
+        adler.update(&data[..12_345]);
+        adler.update(&data[12_345..]);
+        assert_eq!(adler.checksum(), adler32(&data));
+    }
+}
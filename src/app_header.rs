@@ -0,0 +1,67 @@
+use std::path::Path;
+
+/**
+* The application header xdelta3 writes: `name/comp/srcname/srccomp`, or `name/comp` without a
+* source file. Names are the file names given to the encoder, the compression fields name an
+* external compressor such as `G` for gzip and are empty for uncompressed files.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppHeader {
+  pub target_name: Option<String>,
+  pub target_compression: Option<String>,
+  pub source_name: Option<String>,
+  pub source_compression: Option<String>,
+}
+
+impl AppHeader {
+  /// Parses the raw header, `None` if it isn't in xdelta3's format
+  pub fn parse(bytes: &[u8]) -> Option<AppHeader> {
+    let fields : Vec<Option<String>> = bytes.split(|&b| b == b'/')
+      .map(|field| if field.is_empty() { None } else { Some(String::from_utf8_lossy(field).into_owned()) })
+      .collect();
+    match fields.len() {
+      2 | 4 => {
+        let mut fields = fields.into_iter();
+        Some(AppHeader {
+          target_name: fields.next().and_then(|f| f),
+          target_compression: fields.next().and_then(|f| f),
+          source_name: fields.next().and_then(|f| f),
+          source_compression: fields.next().and_then(|f| f),
+        })
+      },
+      _ => None,
+    }
+  }
+
+  /// Whether `path` has the file name the patch was created from, true if the patch doesn't name one
+  pub fn matches_source<P: AsRef<Path>>(&self, path: P) -> bool {
+    match (self.source_name.as_ref(), path.as_ref().file_name()) {
+      (None, _) => true,
+      (Some(name), Some(file_name)) => Path::new(name).file_name() == Some(file_name),
+      (Some(_), None) => false,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::AppHeader;
+
+  #[test]
+  fn xdelta3_formats() {
+    let header = AppHeader::parse(b"new.bin//old.bin/").unwrap();
+    assert_eq!(header.target_name.as_deref(), Some("new.bin"));
+    assert_eq!(header.source_name.as_deref(), Some("old.bin"));
+    assert!(header.target_compression.is_none() && header.source_compression.is_none());
+    assert!(header.matches_source("/data/old.bin"));
+    assert!(!header.matches_source("/data/other.bin"));
+
+    let header = AppHeader::parse(b"new.tar.gz/G").unwrap();
+    assert_eq!(header.target_compression.as_deref(), Some("G"));
+    assert_eq!(header.source_name, None);
+    assert!(header.matches_source("anything"));
+
+    assert_eq!(AppHeader::parse(b"not an xdelta3 header"), None);
+    assert_eq!(AppHeader::parse(b"a/b/c"), None);
+  }
+}
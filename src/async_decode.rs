@@ -0,0 +1,430 @@
+//! `decode_async`, available with the `async` feature.
+//!
+//! The patch is read as it arrives: the header and each window are parsed as soon as all of their
+//! bytes are buffered. A window is then decoded in memory and written out before the next one is
+//! read. VCD_SOURCE windows read the parts of their source segment that their COPYs use, with one
+//! seek and read per range of the source, before the window is decoded.
+
+use patch_reader::{code_table, SectionDecompressors};
+use reader::Reader;
+use vcdiff_code_table::CodeTable;
+use vcdiff_instructions::Operation;
+use vcdiff_header::Header;
+use vcdiff_source::Source;
+use vcdiff_target::HistoryTarget;
+use vcdiff_window::{check_window_indicator, Window};
+use decode_options::{check_limit, DecodeLimits, DecodeOptions};
+use xdelta_error::XdeltaError;
+use decode_base7_int;
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use std::future::Future;
+use std::io::{Cursor, SeekFrom};
+use std::mem;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Bytes requested from the patch per read
+const READ_SIZE: usize = 16 * 1024;
+
+/**
+* Applies the VCDIFF/xdelta3 patch read from `patch` to `source` and writes the result to `target`.
+* The returned future resolves once the target is flushed.
+*/
+pub fn decode_async<S, P, T>(source: Option<S>, patch: P, target: T) -> DecodeAsync<S, P, T>
+  where S: AsyncRead + AsyncSeek + Unpin, P: AsyncRead + Unpin, T: AsyncWrite + Unpin {
+  decode_async_with_options(source, patch, target, &DecodeOptions::default())
+}
+
+/**
+* Same as `decode_async`, with `DecodeOptions`. `target_history` bounds the output kept in
+* memory for VCD_TARGET windows like for `decode_to_writer`, by default to the largest window so far.
+*/
+pub fn decode_async_with_options<S, P, T>(source: Option<S>, patch: P, target: T, options: &DecodeOptions) -> DecodeAsync<S, P, T>
+  where S: AsyncRead + AsyncSeek + Unpin, P: AsyncRead + Unpin, T: AsyncWrite + Unpin {
+  DecodeAsync {
+    source,
+    patch,
+    target,
+    output: HistoryTarget::new(Vec::new(), options.target_history),
+    options: options.clone(),
+    input: Vec::new(),
+    patch_ended: false,
+    code_table: Arc::new(CodeTable::default()),
+    sections: None,
+    index: 0,
+    written: 0,
+    state: State::Header,
+  }
+}
+
+enum State {
+  Header,
+  Window,
+  SeekSource { window: Window, reads: SourceReads, next: usize },
+  PollSeek { window: Window, reads: SourceReads, next: usize },
+  ReadSource { window: Window, reads: SourceReads, next: usize, filled: usize },
+  Write,
+  Flush,
+  Done,
+}
+
+/// The future returned by `decode_async`
+pub struct DecodeAsync<S, P, T> {
+  source: Option<S>,
+  patch: P,
+  target: T,
+  /// the output of the current window, kept as history for VCD_TARGET windows
+  output: HistoryTarget<Vec<u8>>,
+  options: DecodeOptions,
+  /// patch bytes that were read but not parsed yet
+  input: Vec<u8>,
+  patch_ended: bool,
+  code_table: Arc<CodeTable>,
+  sections: Option<SectionDecompressors>,
+  index: u64,
+  /// bytes of the current window's output that reached the target
+  written: usize,
+  state: State,
+}
+
+/// Parses a structure from the front of `input` if it's complete, returning it and its length
+fn parse<'a, F, R>(input: &'a [u8], read: F) -> Result<Option<(R, usize)>, XdeltaError> where F: FnOnce(&mut Reader<Cursor<&'a [u8]>>) -> Result<R, XdeltaError> {
+  // a one byte buffer never reads ahead, so the cursor position tells what was consumed
+  let mut bytes = Reader::with_capacity(1, Cursor::new(input));
+  let result = read(&mut bytes);
+  let consumed = bytes.get_ref().position() as usize;
+  match result {
+    Ok(value) => Ok(Some((value, consumed))),
+    Err(XdeltaError::UnexpectedEof) | Err(XdeltaError::TruncatedVarint) if consumed == input.len() => Ok(None),
+    Err(err) => Err(err),
+  }
+}
+
+/// The length of the window at the front of `input`, `None` until the fields up to
+/// the delta encoding length are buffered
+fn window_length(input: &[u8], limits: &DecodeLimits) -> Result<Option<usize>, XdeltaError> {
+  let mut bytes = input.iter();
+  let window_indicator = match bytes.next() {
+    Some(&indicator) => indicator,
+    None => return Ok(None),
+  };
+  check_window_indicator(window_indicator)?;
+  let varints = if window_indicator & 3 != 0 { 3 } else { 1 }; //segment length and position
+  let mut delta_encoding_length = 0;
+  for _ in 0..varints {
+    match decode_base7_int(&mut bytes) {
+      Ok(value) => delta_encoding_length = value.result,
+      Err(_) if bytes.len() == 0 => return Ok(None),
+      Err(err) => return Err(err),
+    }
+  }
+  check_limit("max_window_size", limits.max_window_size, delta_encoding_length)?;
+  let header_length = input.len() - bytes.len();
+  Ok(Some(header_length.saturating_add(delta_encoding_length as usize)))
+}
+
+/// The source bytes the COPYs of one window read, fetched ahead of decoding it
+struct SourceReads {
+  /// disjoint ranges sorted by their source position, with their bytes
+  ranges: Vec<(u64, Vec<u8>)>,
+}
+
+impl SourceReads {
+  /// The ranges of the source segment of `window` that its COPYs read, at most as many bytes as
+  /// the target window length
+  fn new(window: &Window, code_table: &CodeTable) -> Result<SourceReads, XdeltaError> {
+    let (segment_length, position) = window.source_segment.unwrap();
+    let mut copies = Vec::new();
+    for operation in window.instructions(code_table) {
+      if let Operation::Copy { address, size, .. } = operation? {
+        if address < segment_length {
+          // a copy crossing the end of the segment fails when the window is decoded
+          copies.push((position + address, position + address + size.min(segment_length - address)));
+        }
+      }
+    }
+    copies.sort_unstable();
+    let mut merged : Vec<(u64, u64)> = Vec::new();
+    for (start, end) in copies {
+      match merged.last_mut() {
+        Some(last) if start <= last.1 => last.1 = last.1.max(end),
+        _ => merged.push((start, end)),
+      }
+    }
+    Ok(SourceReads { ranges: merged.into_iter().map(|(start, end)| (start, vec![0; (end - start) as usize])).collect() })
+  }
+}
+
+impl Source for SourceReads {
+  fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> Result<(), XdeltaError> {
+    let index = self.ranges.partition_point(|&(start, ref bytes)| start + bytes.len() as u64 <= pos);
+    match self.ranges.get(index) {
+      Some(&(start, ref bytes)) if start <= pos => (&bytes[..]).read_at(pos - start, buf),
+      _ => Err(XdeltaError::InvalidWindow("copy reaches outside of the source that was read")),
+    }
+  }
+}
+
+impl<S, P, T> DecodeAsync<S, P, T> where S: AsyncRead + AsyncSeek + Unpin, P: AsyncRead + Unpin, T: AsyncWrite + Unpin {
+  /// Reads more of the patch into `input`
+  fn poll_patch(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), XdeltaError>> {
+    let mut chunk = [0u8; READ_SIZE];
+    let mut buf = ReadBuf::new(&mut chunk);
+    match Pin::new(&mut self.patch).poll_read(cx, &mut buf) {
+      Poll::Pending => return Poll::Pending,
+      Poll::Ready(result) => result?,
+    }
+    if buf.filled().is_empty() {
+      self.patch_ended = true;
+    }
+    self.input.extend_from_slice(buf.filled());
+    Poll::Ready(Ok(()))
+  }
+
+  fn decode(&mut self, window: Window, reads: Option<SourceReads>) -> Result<(), XdeltaError> {
+    let mut source = reads;
+    window.decode_window(self.index, &mut source, &mut self.output, &self.code_table, &self.options)?;
+    self.index += 1;
+    self.state = State::Write;
+    Ok(())
+  }
+
+  /// Makes one step of progress, returns true once the whole patch is applied
+  fn poll_step(&mut self, cx: &mut Context<'_>) -> Poll<Result<bool, XdeltaError>> {
+    match mem::replace(&mut self.state, State::Done) {
+      State::Header => {
+        match parse(&self.input, |bytes| Header::new(bytes, &self.options.limits))? {
+          Some((header, length)) => {
+            self.input.drain(..length);
+            self.code_table = Arc::new(code_table(&header, &self.options.limits)?);
+            self.sections = Some(SectionDecompressors::new(&header, &self.options.secondary, &self.options.limits)?);
+            self.state = State::Window;
+          },
+          None if self.patch_ended => return Poll::Ready(Err(XdeltaError::UnexpectedEof)),
+          None => {
+            self.state = State::Header;
+            return self.poll_patch(cx).map(|result| result.map(|_| false));
+          },
+        }
+      },
+      State::Window => {
+        if self.input.is_empty() && self.patch_ended {
+          self.state = State::Flush;
+          return Poll::Ready(Ok(false));
+        }
+        let length = match window_length(&self.input, &self.options.limits)? {
+          Some(length) if length <= self.input.len() => length,
+          _ if self.patch_ended => return Poll::Ready(Err(XdeltaError::UnexpectedEof)),
+          _ => {
+            self.state = State::Window;
+            return self.poll_patch(cx).map(|result| result.map(|_| false));
+          },
+        };
+        let mut window = match parse(&self.input[..length], |bytes| Window::new(bytes, &self.options.limits))? {
+          Some((window, _)) => window,
+          None => return Poll::Ready(Err(XdeltaError::UnexpectedEof)),
+        };
+        self.input.drain(..length);
+        self.sections.as_mut().unwrap().decompress(&mut window)?;
+        match window.source_segment {
+          Some(_) if window.window_indicator & 1 != 0 && self.source.is_some() => {
+            let reads = SourceReads::new(&window, &self.code_table)?;
+            self.state = State::SeekSource { window, reads, next: 0 };
+          },
+          _ => self.decode(window, None)?,
+        }
+      },
+      State::SeekSource { window, reads, next } => {
+        if next == reads.ranges.len() {
+          self.decode(window, Some(reads))?;
+          return Poll::Ready(Ok(false));
+        }
+        Pin::new(self.source.as_mut().unwrap()).start_seek(SeekFrom::Start(reads.ranges[next].0))?;
+        self.state = State::PollSeek { window, reads, next };
+      },
+      State::PollSeek { window, reads, next } => {
+        match Pin::new(self.source.as_mut().unwrap()).poll_complete(cx) {
+          Poll::Pending => {
+            self.state = State::PollSeek { window, reads, next };
+            return Poll::Pending;
+          },
+          Poll::Ready(result) => result?,
+        };
+        self.state = State::ReadSource { window, reads, next, filled: 0 };
+      },
+      State::ReadSource { window, mut reads, next, mut filled } => {
+        if filled == reads.ranges[next].1.len() {
+          self.state = State::SeekSource { window, reads, next: next + 1 };
+          return Poll::Ready(Ok(false));
+        }
+        let mut buf = ReadBuf::new(&mut reads.ranges[next].1[filled..]);
+        match Pin::new(self.source.as_mut().unwrap()).poll_read(cx, &mut buf) {
+          Poll::Pending => {
+            self.state = State::ReadSource { window, reads, next, filled };
+            return Poll::Pending;
+          },
+          Poll::Ready(result) => result?,
+        }
+        if buf.filled().is_empty() {
+          return Poll::Ready(Err(XdeltaError::InvalidWindow("source segment reaches past the end of the source")));
+        }
+        filled += buf.filled().len();
+        self.state = State::ReadSource { window, reads, next, filled };
+      },
+      State::Write => {
+        let pending = &self.output.get_mut()[self.written..];
+        if pending.is_empty() {
+          self.output.get_mut().clear();
+          self.written = 0;
+          self.state = State::Window;
+          return Poll::Ready(Ok(false));
+        }
+        self.state = State::Write;
+        match Pin::new(&mut self.target).poll_write(cx, pending) {
+          Poll::Pending => return Poll::Pending,
+          Poll::Ready(Ok(0)) => return Poll::Ready(Err(XdeltaError::Io(std::io::ErrorKind::WriteZero.into()))),
+          Poll::Ready(result) => self.written += result?,
+        }
+      },
+      State::Flush => {
+        self.state = State::Flush;
+        match Pin::new(&mut self.target).poll_flush(cx) {
+          Poll::Pending => return Poll::Pending,
+          Poll::Ready(result) => result?,
+        }
+        return Poll::Ready(Ok(true));
+      },
+      State::Done => panic!("DecodeAsync polled after completion"),
+    }
+    Poll::Ready(Ok(false))
+  }
+}
+
+impl<S, P, T> Future for DecodeAsync<S, P, T> where S: AsyncRead + AsyncSeek + Unpin, P: AsyncRead + Unpin, T: AsyncWrite + Unpin {
+  type Output = Result<(), XdeltaError>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), XdeltaError>> {
+    let this = self.get_mut();
+    loop {
+      match this.poll_step(cx) {
+        Poll::Pending => return Poll::Pending,
+        Poll::Ready(Ok(false)) => {},
+        Poll::Ready(Ok(true)) => {
+          this.state = State::Done;
+          return Poll::Ready(Ok(()));
+        },
+        Poll::Ready(Err(err)) => {
+          this.state = State::Done;
+          return Poll::Ready(Err(err));
+        },
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{decode_async, decode_async_with_options};
+  use tests::{ADD_ONLY_PATCH, VCD_TARGET_PATCH, VCD_TARGET_OUTPUT};
+  use {decode_to_vec, encode, DecodeLimits, DecodeOptions, PatchInspector, XdeltaError};
+  use tokio::io::{AsyncRead, ReadBuf};
+  use tokio::runtime::Builder;
+  use std::io::{self, Cursor};
+  use std::pin::Pin;
+  use std::task::{Context, Poll};
+
+  /// A patch stream that hands out a few bytes at a time and is not ready on every other poll
+  struct Trickle {
+    bytes: Vec<u8>,
+    pos: usize,
+    ready: bool,
+  }
+
+  impl AsyncRead for Trickle {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+      self.ready = !self.ready;
+      if !self.ready {
+        cx.waker().wake_by_ref();
+        return Poll::Pending;
+      }
+      let len = buf.remaining().min(3).min(self.bytes.len() - self.pos);
+      buf.put_slice(&self.bytes[self.pos..self.pos + len]);
+      self.pos += len;
+      Poll::Ready(Ok(()))
+    }
+  }
+
+  fn trickle(bytes: &[u8]) -> Trickle {
+    Trickle { bytes: bytes.to_vec(), pos: 0, ready: false }
+  }
+
+  #[test]
+  fn decodes_a_trickling_patch() {
+    let source = b"an async source, read with one seek per window".to_vec();
+    let target = b"an async target, source bytes and a run: zzzzzzzzzzzzzzzz, then source bytes again: an async source".to_vec();
+    let patch = encode(&source, &target);
+    let runtime = Builder::new_current_thread().build().unwrap();
+
+    let mut output = Vec::new();
+    runtime.block_on(decode_async(Some(Cursor::new(source.clone())), trickle(&patch), &mut output)).unwrap();
+    assert_eq!(output, target);
+    assert_eq!(output, decode_to_vec(&source, &patch).unwrap());
+
+    let result = runtime.block_on(decode_async(Some(Cursor::new(source.clone())), trickle(&patch[..patch.len() - 1]), Vec::new()));
+    assert!(matches!(result, Err(XdeltaError::UnexpectedEof)));
+    let result = runtime.block_on(decode_async(None::<Cursor<Vec<u8>>>, trickle(b"PK\x03\x04\x00"), Vec::new()));
+    assert!(matches!(result, Err(XdeltaError::InvalidMagic(_))));
+  }
+
+  #[test]
+  fn bounded_target_history() {
+    let runtime = Builder::new_current_thread().build().unwrap();
+    let mut output = Vec::new();
+    runtime.block_on(decode_async(None::<Cursor<Vec<u8>>>, trickle(&VCD_TARGET_PATCH), &mut output)).unwrap();
+    assert_eq!(output, VCD_TARGET_OUTPUT);
+
+    // the last window copies target[0..20], more than the 20 bytes kept after the second window
+    let mut patch = VCD_TARGET_PATCH;
+    patch[39] = 0x00;
+    let result = runtime.block_on(decode_async(None::<Cursor<Vec<u8>>>, trickle(&patch), Vec::new()));
+    assert!(matches!(result, Err(XdeltaError::InvalidWindow("copy reaches before the retained target history"))), "{:?}", result);
+    let options = DecodeOptions { target_history: Some(usize::MAX), ..DecodeOptions::default() };
+    let mut output = Vec::new();
+    runtime.block_on(decode_async_with_options(None::<Cursor<Vec<u8>>>, trickle(&patch), &mut output, &options)).unwrap();
+    assert_eq!(output, [&VCD_TARGET_OUTPUT[..28], &VCD_TARGET_OUTPUT[..20]].concat());
+  }
+
+  #[test]
+  fn reads_only_the_copied_source() {
+    let source : Vec<u8> = (0..4096u32).map(|i| (i * 7 % 251) as u8).collect();
+    let target = [&source[3000..3100], &b"some new bytes"[..], &source[100..200]].concat();
+    let patch = encode(&source, &target);
+    let window = PatchInspector::new(&patch[..]).unwrap().next().unwrap().unwrap();
+    assert!(window.source_segment.unwrap().0 > 1024);
+
+    // the segment is larger than a window may be, the two copies aren't
+    let options = DecodeOptions { limits: DecodeLimits { max_window_size: 1024, ..DecodeLimits::default() }, ..DecodeOptions::default() };
+    let runtime = Builder::new_current_thread().build().unwrap();
+    let mut output = Vec::new();
+    runtime.block_on(decode_async_with_options(Some(Cursor::new(source.clone())), trickle(&patch), &mut output, &options)).unwrap();
+    assert_eq!(output, target);
+
+    let result = runtime.block_on(decode_async(Some(Cursor::new(source[..3050].to_vec())), trickle(&patch), Vec::new()));
+    assert!(matches!(result, Err(XdeltaError::InvalidWindow("source segment reaches past the end of the source"))), "{:?}", result);
+  }
+
+  #[test]
+  fn delta_encoding_length_mismatch() {
+    // the window claims one byte more than it has, the same patch fails to decode synchronously
+    let mut patch = ADD_ONLY_PATCH.to_vec();
+    patch[6] = 0x0A;
+    patch.push(0x00);
+    assert!(matches!(decode_to_vec(&[], &patch), Err(XdeltaError::InvalidWindow(_))));
+    let runtime = Builder::new_current_thread().build().unwrap();
+    let result = runtime.block_on(decode_async(None::<Cursor<Vec<u8>>>, trickle(&patch), Vec::new()));
+    assert!(matches!(result, Err(XdeltaError::InvalidWindow("delta encoding length doesn't match the window"))), "{:?}", result);
+  }
+}
@@ -0,0 +1,208 @@
+//! Decode-only command line tool that accepts the options of `xdelta3 -d` and exits the way it does:
+//! 0 on success, 1 on any failure.
+
+extern crate xdelta;
+
+use xdelta::{DecodeOptions, PatchInspector};
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+
+const USAGE: &str = "usage: xdelta -d [-cfnq] [-s source] [patch [target]]
+  -d           decompress (the only supported mode)
+  -s source    source file to copy from, the one named in the patch by default
+  -c           write the target to standard output
+  -f           overwrite an existing target file
+  -n           don't verify window checksums
+  -q           quiet, don't print errors
+  -h           show this help
+  -V           show the version
+the patch is read from standard input when it's missing or \"-\"";
+
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+  decode: bool,
+  source: Option<String>,
+  patch: Option<String>,
+  target: Option<String>,
+  stdout: bool,
+  force: bool,
+  no_verify: bool,
+  quiet: bool,
+  help: bool,
+  version: bool,
+}
+
+/// Parses xdelta3 style options, single letter flags can be combined as in `-dfs source`
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+  let mut parsed = Args::default();
+  let mut files = Vec::new();
+  while let Some(arg) = args.next() {
+    if arg == "--" {
+      files.extend(args.by_ref());
+      break;
+    }
+    if !arg.starts_with('-') || arg == "-" {
+      files.push(arg);
+      continue;
+    }
+    let flags = &arg[1..];
+    for (i, flag) in flags.char_indices() {
+      match flag {
+        'd' => parsed.decode = true,
+        'c' => parsed.stdout = true,
+        'f' => parsed.force = true,
+        'n' => parsed.no_verify = true,
+        'q' => parsed.quiet = true,
+        'h' => parsed.help = true,
+        'V' => parsed.version = true,
+        's' => {
+          let rest = &flags[i + 1..];
+          let source = if rest.is_empty() { args.next() } else { Some(rest.to_string()) };
+          match source {
+            Some(source) => parsed.source = Some(source),
+            None => return Err("-s requires a source file".to_string()),
+          }
+          break;
+        },
+        'e' => return Err("only decoding (-d) is supported".to_string()),
+        other => return Err(format!("invalid option: -{}", other)),
+      }
+    }
+  }
+  if files.len() > 2 {
+    return Err(format!("too many file names: {}", files[2]));
+  }
+  let mut files = files.into_iter();
+  parsed.patch = files.next().filter(|patch| patch != "-");
+  parsed.target = files.next();
+  Ok(parsed)
+}
+
+/// The last component of a file name recorded in the patch
+fn file_name(recorded: Option<String>) -> Option<String> {
+  recorded.and_then(|name| Path::new(&name).file_name().map(|name| name.to_string_lossy().into_owned()))
+}
+
+/// A hidden file in the directory of `target`, unique to this process
+fn temp_path(target: &Path) -> PathBuf {
+  let name = target.file_name().map_or_else(|| "target".into(), |name| name.to_string_lossy());
+  target.with_file_name(format!(".{}.xdelta-{}", name, process::id()))
+}
+
+fn run(args: &Args) -> Result<(), String> {
+  if !args.decode {
+    return Err("only decoding (-d) is supported".to_string());
+  }
+  // standard output can't be read back, VCD_TARGET windows copy from at most the largest window so far
+  let options = DecodeOptions { verify_checksums: !args.no_verify, target_history: None, ..DecodeOptions::default() };
+  let patch : Box<dyn Read> = match args.patch {
+    Some(ref path) => Box::new(File::open(path).map_err(|err| format!("{}: {}", path, err))?),
+    None => Box::new(io::stdin()),
+  };
+
+  // the header is read once, a patch from a pipe can't be opened again
+  let patch = PatchInspector::with_options(patch, &options).map_err(|err| err.to_string())?;
+
+  // like xdelta3, fall back to the file names recorded in the patch, without their directories
+  let app_header = patch.header().app_header();
+  if let (Some(source), Some(header)) = (args.source.as_ref(), app_header.as_ref()) {
+    if !header.matches_source(source) && !args.quiet {
+      eprintln!("xdelta: warning: the patch was made from {}, not {}", header.source_name.as_ref().unwrap(), source);
+    }
+  }
+  let (recorded_target, recorded_source) = match app_header {
+    Some(header) => (file_name(header.target_name), file_name(header.source_name)),
+    None => (None, None),
+  };
+  let source = match args.source.clone().or(recorded_source) {
+    Some(path) => Some(File::open(&path).map_err(|err| format!("{}: {}", path, err))?),
+    None => None,
+  };
+  let target_path = match (args.target.as_ref(), recorded_target) {
+    _ if args.stdout || args.patch.is_none() && args.target.is_none() => None,
+    (Some(path), _) => Some(path.clone()),
+    (None, Some(name)) => Some(name),
+    (None, None) => return Err("target file name required, or use -c for standard output".to_string()),
+  };
+  match target_path {
+    Some(path) => {
+      if !args.force && Path::new(&path).exists() {
+        return Err(format!("to overwrite output file specify -f: {}", path));
+      }
+      // decoded next to the target and renamed once it's complete, a failed decoding leaves no partial file
+      let temp_path = temp_path(Path::new(&path));
+      let temp = OpenOptions::new().read(true).write(true).create_new(true).open(&temp_path)
+        .map_err(|err| format!("{}: {}", temp_path.display(), err))?;
+      let result = patch.decode(source, temp, &options).map_err(|err| err.to_string())
+        .and_then(|()| fs::rename(&temp_path, &path).map_err(|err| format!("{}: {}", path, err)));
+      if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+      }
+      result
+    },
+    None => {
+      let stdout = io::stdout();
+      let mut stdout = stdout.lock();
+      patch.decode_to_writer(source, &mut stdout, &options).map_err(|err| err.to_string())?;
+      stdout.flush().map_err(|err| format!("standard output: {}", err))
+    },
+  }
+}
+
+fn main() {
+  let args = match parse_args(env::args().skip(1)) {
+    Ok(args) => args,
+    Err(message) => {
+      eprintln!("xdelta: {}\n{}", message, USAGE);
+      process::exit(EXIT_FAILURE);
+    },
+  };
+  if args.help {
+    println!("{}", USAGE);
+    process::exit(EXIT_SUCCESS);
+  }
+  if args.version {
+    println!("xdelta version {}", env!("CARGO_PKG_VERSION"));
+    process::exit(EXIT_SUCCESS);
+  }
+  match run(&args) {
+    Ok(()) => process::exit(EXIT_SUCCESS),
+    Err(message) => {
+      if !args.quiet {
+        eprintln!("xdelta: {}", message);
+      }
+      process::exit(EXIT_FAILURE);
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{parse_args, Args};
+
+  fn parse(line: &str) -> Result<Args, String> {
+    parse_args(line.split_whitespace().map(String::from))
+  }
+
+  #[test]
+  fn xdelta3_style_arguments() {
+    let args = parse("-d -s old.bin patch.vcdiff new.bin").unwrap();
+    assert_eq!(args, Args { decode: true, source: Some("old.bin".into()), patch: Some("patch.vcdiff".into()), target: Some("new.bin".into()), ..Args::default() });
+
+    let args = parse("-dcfs old.bin -").unwrap();
+    assert!(args.decode && args.stdout && args.force && args.patch.is_none());
+    assert_eq!(args.source, Some("old.bin".to_string()));
+    assert_eq!(parse("-dsold.bin").unwrap().source, Some("old.bin".to_string()));
+
+    assert!(parse("-d -s").is_err());
+    assert!(parse("-e -s old new patch").is_err());
+    assert!(parse("-d a b c").is_err());
+  }
+}
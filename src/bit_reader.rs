@@ -0,0 +1,56 @@
+use xdelta_error::XdeltaError;
+
+/// Reads single bits the way xdelta3's secondary compressors write them:
+/// least significant bit of each byte first, multi-bit values most significant bit first.
+pub struct BitReader<'a> {
+    input: &'a [u8],
+    cur_byte: u8,
+    cur_mask: u16,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(input: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            input,
+            cur_byte: 0,
+            cur_mask: 0x100,
+        }
+    }
+
+    pub fn bit(&mut self) -> Result<bool, XdeltaError> {
+        if self.cur_mask == 0x100 {
+            match self.input.split_first() {
+                Some((&byte, rest)) => {
+                    self.cur_byte = byte;
+                    self.cur_mask = 1;
+                    self.input = rest;
+                }
+                None => return Err(XdeltaError::InvalidSecondaryData("secondary decoder end of input")),
+            }
+        }
+        let bit = (self.cur_byte as u16 & self.cur_mask) != 0;
+        self.cur_mask <<= 1;
+        Ok(bit)
+    }
+
+    pub fn bits(&mut self, count: usize) -> Result<usize, XdeltaError> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = (value << 1) | self.bit()? as usize;
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BitReader;
+
+    #[test]
+    fn lsb_first_bytes_msb_first_values() {
+        let mut bits = BitReader::new(&[0b1000_0101, 0b0000_0001]);
+        assert_eq!(bits.bits(3).unwrap(), 0b101);
+        assert_eq!(bits.bits(6).unwrap(), 0b00_0011);
+        assert!(bits.bits(8).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use xdelta_error::XdeltaError;
+
+use std::convert::TryInto;
+
+const MAGIC: [u8; 4] = *b"XDCK";
+const ENCODED_LENGTH: usize = 4 + 4 * 8;
+
+/**
+* How far an interrupted decoding got: the windows that were completely applied, where the next
+* window starts in the patch and how much of the target was written. The length of the patch
+* tells it from other patches when resuming.
+* Secondary decompressors keep state from one window to the next (e.g. the LZMA streams), which
+* can't be saved. Resuming a patch with secondary compression reads the compressed sections of
+* the completed windows again to rebuild it, without executing their instructions.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+  /// Number of windows applied
+  pub windows: u64,
+  /// Position of the next window in the patch
+  pub patch_offset: u64,
+  /// Length of the whole patch
+  pub patch_length: u64,
+  /// Bytes of the target written by the applied windows
+  pub target_offset: u64,
+}
+
+impl Checkpoint {
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(ENCODED_LENGTH);
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&self.windows.to_be_bytes());
+    bytes.extend_from_slice(&self.patch_offset.to_be_bytes());
+    bytes.extend_from_slice(&self.patch_length.to_be_bytes());
+    bytes.extend_from_slice(&self.target_offset.to_be_bytes());
+    bytes
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> Result<Checkpoint, XdeltaError> {
+    if bytes.len() != ENCODED_LENGTH || bytes[..4] != MAGIC {
+      return Err(XdeltaError::InvalidCheckpoint("not a saved checkpoint"));
+    }
+    let field = |index: usize| u64::from_be_bytes(bytes[4 + index * 8..][..8].try_into().unwrap());
+    Ok(Checkpoint { windows: field(0), patch_offset: field(1), patch_length: field(2), target_offset: field(3) })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Checkpoint;
+
+  #[test]
+  fn serialization() {
+    let checkpoint = Checkpoint { windows: 3, patch_offset: 1 << 40, patch_length: 1 << 41, target_offset: 12345 };
+    let bytes = checkpoint.to_bytes();
+    assert_eq!(Checkpoint::from_bytes(&bytes).unwrap(), checkpoint);
+    assert!(Checkpoint::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert!(Checkpoint::from_bytes(&[0; 36]).is_err());
+  }
+}
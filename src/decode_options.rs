@@ -0,0 +1,75 @@
+use secondary_decompressor::SecondaryRegistry;
+use xdelta_error::XdeltaError;
+
+/// Settings for `decode_with_options`
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+  /// Check every target window against its VCD_ADLER32 checksum, when the patch has one
+  pub verify_checksums: bool,
+  /// Write the target in chunks of at most this many bytes while a window is decoded, instead of
+  /// buffering the whole window. Caps the memory used for output at the cost of reading earlier
+  /// bytes of the window back from the target. Only targets that are read back by seeking are
+  /// written in chunks, `decode_to_writer` and `decode_async` keep their windows in memory for
+  /// VCD_TARGET windows anyway. The sections of a window are still read whole.
+  ///
+  /// The VCD_ADLER32 checksum of a window is only known to match once the whole window is decoded.
+  /// On `ChecksumMismatch` the chunks of the corrupt window were written already, the target is
+  /// then back at the start of that window and has to be truncated there, like `decode_file` does.
+  pub stream_chunk_size: Option<usize>,
+  /// How many bytes of earlier output `decode_to_writer` keeps for VCD_TARGET windows. `None` keeps
+  /// as many as the largest target window so far, which covers segments that reach back to the
+  /// start of the previous window. `Some(usize::MAX)` keeps all of it.
+  pub target_history: Option<usize>,
+  /// Decompressors for the secondary compressor ids a patch may use
+  pub secondary: SecondaryRegistry,
+  /// Sizes a patch may ask for before it's rejected, see `DecodeLimits`
+  pub limits: DecodeLimits,
+}
+
+impl Default for DecodeOptions {
+  fn default() -> DecodeOptions {
+    DecodeOptions {
+      verify_checksums: true,
+      stream_chunk_size: None,
+      target_history: None,
+      secondary: SecondaryRegistry::default(),
+      limits: DecodeLimits::default(),
+    }
+  }
+}
+
+/**
+* Upper bounds on the sizes read from a patch, which is what memory is allocated for. Patches
+* from untrusted sources can't make the decoder allocate more than these, exceeding one fails
+* with `XdeltaError::LimitExceeded`.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+  /// Largest target window, delta encoding and section of a window (also after secondary
+  /// decompression). `decode_async` holds the source bytes a window copies in memory, which are
+  /// no more than its target window.
+  /// The default of 64 MiB is well above the 8 MiB windows xdelta3 writes by default.
+  pub max_window_size: u64,
+  /// Largest target the patch may produce, unlimited by default
+  pub max_target_size: u64,
+  /// Largest application header and application-defined code table in the file header
+  pub max_app_header: u64,
+}
+
+impl Default for DecodeLimits {
+  fn default() -> DecodeLimits {
+    DecodeLimits {
+      max_window_size: 64 << 20,
+      max_target_size: u64::MAX,
+      max_app_header: 1 << 20,
+    }
+  }
+}
+
+/// Fails with `LimitExceeded` if `value` is above `max`, the value of the limit named `limit`
+pub fn check_limit(limit: &'static str, max: u64, value: u64) -> Result<(), XdeltaError> {
+  if value > max {
+    return Err(XdeltaError::LimitExceeded { limit, value });
+  }
+  Ok(())
+}
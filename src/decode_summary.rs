@@ -0,0 +1,71 @@
+use adler32::Adler32;
+use vcdiff_target::Target;
+use xdelta_error::XdeltaError;
+
+/// What a decoding produced, returned by the `_with_summary` functions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeSummary {
+  /// Number of windows applied
+  pub windows: u64,
+  /// Bytes of the patch read
+  pub patch_bytes: u64,
+  /// Bytes of the target written
+  pub target_bytes: u64,
+  /// What the `TargetHasher` computed over the whole target
+  pub digest: Vec<u8>,
+}
+
+/**
+* Hashes the target while it is written, so it can be compared with a manifest without reading
+* the file again. `Adler32` gives the Adler-32 of the whole target as 4 big-endian bytes, other
+* digests such as SHA-256 or BLAKE3 plug in by implementing this trait.
+*/
+pub trait TargetHasher {
+  /// Called with the bytes of the target in order
+  fn update(&mut self, bytes: &[u8]);
+  /// The digest of everything passed to `update`
+  fn finish(&mut self) -> Vec<u8>;
+}
+
+impl TargetHasher for Adler32 {
+  fn update(&mut self, bytes: &[u8]) {
+    Adler32::update(self, bytes);
+  }
+
+  fn finish(&mut self) -> Vec<u8> {
+    self.checksum().to_be_bytes().to_vec()
+  }
+}
+
+/// Passes everything written to the target on to `hasher` as well
+pub struct HashingTarget<T, H> {
+  pub inner: T,
+  pub hasher: H,
+}
+
+impl<T: Target, H: TargetHasher> Target for HashingTarget<T, H> {
+  fn write_all(&mut self, buf: &[u8]) -> Result<(), XdeltaError> {
+    self.hasher.update(buf);
+    self.inner.write_all(buf)
+  }
+
+  fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> Result<(), XdeltaError> {
+    self.inner.read_at(pos, buf)
+  }
+
+  fn position(&self) -> u64 {
+    self.inner.position()
+  }
+
+  fn end_window(&mut self) {
+    self.inner.end_window();
+  }
+
+  fn streams_windows(&self) -> bool {
+    self.inner.streams_windows()
+  }
+
+  fn rewind(&mut self, position: u64) -> Result<(), XdeltaError> {
+    self.inner.rewind(position)
+  }
+}
@@ -0,0 +1,310 @@
+//! Decoder for xdelta3's DJW secondary compression (static Huffman, compressor id 1).
+//!
+//! A section is coded as a number of Huffman tables ("groups") and a group selector for each
+//! fixed-size sector of the output. The code lengths of the tables and the selectors are
+//! themselves Huffman coded after a move-to-front transform with 1/2 run-length coding.
+
+use bit_reader::BitReader;
+use xdelta_error::XdeltaError;
+
+const ALPHABET_SIZE: usize = 256;
+/// Maximum length of an alphabet code
+const MAX_CODELEN: usize = 20;
+/// RUN_0, RUN_1 and one symbol per code length 1..=MAX_CODELEN
+const TOTAL_CODES: usize = MAX_CODELEN + 2;
+const RUN_1: usize = 1;
+/// Number of code lengths that are always transmitted after RUN_0 and RUN_1
+const BASIC_CODES: usize = 5;
+/// RUN_0 and RUN_1, the number of additional code length codes counts from after them
+const RUN_CODES: usize = 2;
+/// Bits used for the number of additional code length codes
+const EXTRA_CODE_BITS: usize = 4;
+/// Bits used for each code length of the code length code
+const CLCLEN_BITS: usize = 4;
+/// Bits used for the number of groups minus one
+const GROUP_BITS: usize = 3;
+/// Bits used for the sector size, which is stored divided by SECTORSZ_MULT minus one
+const SECTORSZ_BITS: usize = 5;
+const SECTORSZ_MULT: usize = 5;
+/// Bits used for each code length of the group selector code
+const GBCLEN_BITS: usize = 3;
+
+/// Initial move-to-front order of the code lengths, most frequent first
+static CLEN_MTF_INIT: [u8; TOTAL_CODES - 1] = [0, 4, 5, 6, 7, 8, 9, 10, 3, 11, 2, 12, 13, 1, 14, 15, 16, 17, 18, 19, 20];
+
+fn invalid(details: &'static str) -> XdeltaError {
+    XdeltaError::InvalidSecondaryData(details)
+}
+
+/// Canonical prefix code built from a list of code lengths, 0 meaning the symbol is unused
+struct PrefixCode {
+    /// symbols ordered by code length, then by value
+    inorder: Vec<u8>,
+    base: [usize; TOTAL_CODES + 1],
+    limit: [usize; TOTAL_CODES + 1],
+    min_len: usize,
+    max_len: usize,
+}
+
+impl PrefixCode {
+    fn new(lengths: &[u8]) -> Result<PrefixCode, XdeltaError> {
+        let mut count = [0usize; TOTAL_CODES + 1];
+        for &len in lengths {
+            if len as usize > MAX_CODELEN {
+                return Err(invalid("secondary decoder invalid code length"));
+            }
+            count[len as usize] += 1;
+        }
+
+        let mut code = PrefixCode {
+            inorder: Vec::with_capacity(lengths.len()),
+            base: [0; TOTAL_CODES + 1],
+            limit: [0; TOTAL_CODES + 1],
+            min_len: (1..=MAX_CODELEN).find(|&len| count[len] > 0).unwrap_or(0),
+            max_len: (1..=MAX_CODELEN).rev().find(|&len| count[len] > 0).unwrap_or(0),
+        };
+        if code.min_len == 0 {
+            // no symbol is used, decoding anything with this code fails
+            return Ok(code);
+        }
+
+        // codes of one length are consecutive, the first code of the next length follows
+        // the last one of this length shifted left by one
+        let mut first_index = 0;
+        code.limit[code.min_len] = count[code.min_len] - 1;
+        for len in code.min_len + 1..=code.max_len {
+            let first_code = (code.limit[len - 1] + 1) << 1;
+            first_index += count[len - 1];
+            code.limit[len] = (first_code + count[len]).wrapping_sub(1);
+            code.base[len] = first_code.wrapping_sub(first_index);
+        }
+
+        for len in code.min_len..=code.max_len {
+            for (symbol, _) in lengths.iter().enumerate().filter(|&(_, &l)| l as usize == len) {
+                code.inorder.push(symbol as u8);
+            }
+        }
+        Ok(code)
+    }
+
+    fn symbol(&self, bits: &mut BitReader) -> Result<usize, XdeltaError> {
+        let mut code = 0;
+        let mut len = 0;
+        loop {
+            if len == self.max_len {
+                return Err(invalid("secondary decoder invalid code"));
+            }
+            len += 1;
+            code = (code << 1) | bits.bit()? as usize;
+            if len >= self.min_len && code <= self.limit[len] {
+                break;
+            }
+        }
+        match code.checked_sub(self.base[len]).and_then(|offset| self.inorder.get(offset)) {
+            Some(&symbol) => Ok(symbol as usize),
+            None => Err(invalid("secondary decoder invalid code")),
+        }
+    }
+}
+
+/// Moves `mtf[index]` to the front and returns it
+fn update_mtf(mtf: &mut [u8], index: usize) -> Result<u8, XdeltaError> {
+    if index >= mtf.len() {
+        return Err(invalid("secondary decoder invalid move-to-front index"));
+    }
+    let value = mtf[index];
+    mtf.copy_within(0..index, 1);
+    mtf[0] = value;
+    Ok(value)
+}
+
+/// Decodes `values` coded as move-to-front indices where repeats of the front value are
+/// written as bijective base-2 numbers of RUN_0/RUN_1 symbols. With a `skip_offset`, a value
+/// that was 0 `skip_offset` values earlier is known to be 0 and not coded at all.
+fn decode_1_2(bits: &mut BitReader, code: &PrefixCode, mtf: &mut [u8], skip_offset: usize, values: &mut [u8]) -> Result<(), XdeltaError> {
+    let mut repeat = 0usize;
+    let mut index = 0usize;
+    let mut shift = 0;
+    let mut n = 0;
+    while n < values.len() {
+        if skip_offset != 0 && n >= skip_offset && values[n - skip_offset] == 0 {
+            values[n] = 0;
+        } else if repeat != 0 {
+            values[n] = mtf[0];
+            repeat -= 1;
+        } else if index != 0 {
+            values[n] = update_mtf(mtf, index)?;
+            index = 0;
+        } else {
+            let symbol = code.symbol(bits)?;
+            if symbol <= RUN_1 {
+                if shift >= 32 {
+                    return Err(invalid("secondary decoder invalid repeat code"));
+                }
+                repeat = (symbol + 1) << shift;
+                shift += 1;
+            } else {
+                index = symbol - 1;
+                shift = 0;
+            }
+            continue;
+        }
+        n += 1;
+    }
+    if repeat != 0 {
+        return Err(invalid("secondary decoder invalid repeat code"));
+    }
+    Ok(())
+}
+
+/// Decompresses one DJW coded section, filling all of `output`
+pub fn decode(input: &[u8], output: &mut [u8]) -> Result<(), XdeltaError> {
+    if output.is_empty() {
+        return Ok(());
+    }
+    let mut bits = BitReader::new(input);
+
+    let groups = bits.bits(GROUP_BITS)? + 1;
+    let sector_size = if groups > 1 {
+        (bits.bits(SECTORSZ_BITS)? + 1) * SECTORSZ_MULT
+    } else {
+        output.len()
+    };
+    let sectors = 1 + (output.len() - 1) / sector_size;
+
+    // the code that codes the code lengths
+    let mut cl_lengths = [0u8; TOTAL_CODES];
+    let num_codes = bits.bits(EXTRA_CODE_BITS)? + BASIC_CODES + RUN_CODES;
+    for len in cl_lengths.iter_mut().take(num_codes) {
+        *len = bits.bits(CLCLEN_BITS)? as u8;
+    }
+    let cl_code = PrefixCode::new(&cl_lengths)?;
+
+    // code lengths of every group, a symbol unused in the first group is unused in all of them
+    let mut lengths = vec![0u8; ALPHABET_SIZE * groups];
+    decode_1_2(&mut bits, &cl_code, &mut CLEN_MTF_INIT.clone(), ALPHABET_SIZE, &mut lengths)?;
+    let codes = lengths.chunks(ALPHABET_SIZE).map(PrefixCode::new).collect::<Result<Vec<_>, _>>()?;
+
+    // which group codes each sector
+    let mut selectors = vec![0u8; sectors];
+    if groups > 1 {
+        let mut sel_lengths = vec![0u8; groups + 1];
+        for len in sel_lengths.iter_mut() {
+            *len = bits.bits(GBCLEN_BITS)? as u8;
+        }
+        let sel_code = PrefixCode::new(&sel_lengths)?;
+        let mut sel_mtf : Vec<u8> = (0..groups as u8).collect();
+        decode_1_2(&mut bits, &sel_code, &mut sel_mtf, 0, &mut selectors)?;
+    }
+
+    for (sector, &group) in output.chunks_mut(sector_size).zip(selectors.iter()) {
+        let code = match codes.get(group as usize) {
+            Some(code) => code,
+            None => return Err(invalid("secondary decoder invalid group")),
+        };
+        for byte in sector.iter_mut() {
+            *byte = code.symbol(&mut bits)? as u8;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, CLEN_MTF_INIT};
+
+    /// Writes bits in the order BitReader reads them
+    struct BitWriter {
+        bytes: Vec<u8>,
+        used: usize,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, count: usize, value: usize) {
+            for i in (0..count).rev() {
+                if self.used & 7 == 0 {
+                    self.bytes.push(0);
+                }
+                if (value >> i) & 1 == 1 {
+                    *self.bytes.last_mut().unwrap() |= 1 << (self.used % 8);
+                }
+                self.used += 1;
+            }
+        }
+
+        /// Canonical code of `symbol` for the given code lengths
+        fn symbol(&mut self, lengths: &[usize], symbol: usize) {
+            let mut code = 0;
+            for len in 1..=20 {
+                for (s, _) in lengths.iter().enumerate().filter(|&(_, &l)| l == len) {
+                    if s == symbol {
+                        return self.bits(len, code);
+                    }
+                    code += 1;
+                }
+                code <<= 1;
+            }
+            panic!("symbol {} has no code", symbol);
+        }
+
+        /// Move-to-front plus RUN_0/RUN_1 coding of `values`, mirroring `decode_1_2`
+        fn mtf_1_2(&mut self, lengths: &[usize], mtf: &mut Vec<u8>, skip_offset: usize, values: &[u8]) {
+            let mut run = 0;
+            for n in 0..=values.len() {
+                if n < values.len() && skip_offset != 0 && n >= skip_offset && values[n - skip_offset] == 0 {
+                    continue;
+                }
+                if n < values.len() && values[n] == mtf[0] {
+                    run += 1;
+                    continue;
+                }
+                while run > 0 {
+                    let digit = if run % 2 == 1 { 1 } else { 2 };
+                    self.symbol(lengths, digit - 1);
+                    run = (run - digit) / 2;
+                }
+                if n < values.len() {
+                    let index = mtf.iter().position(|&v| v == values[n]).unwrap();
+                    let value = mtf.remove(index);
+                    mtf.insert(0, value);
+                    self.symbol(lengths, index + 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn two_groups() {
+        let mut group_lengths = vec![0u8; 512];
+        group_lengths[b'a' as usize..b'e' as usize].copy_from_slice(&[1, 2, 3, 3]);
+        group_lengths[256 + b'a' as usize..256 + b'e' as usize].copy_from_slice(&[3, 3, 2, 1]);
+        let expected = b"aaaaaddddaabcaddddcbaaaaadddda".to_vec();
+        let selectors = [0u8, 1, 0, 0, 1, 1];
+
+        let mut w = BitWriter { bytes: Vec::new(), used: 0 };
+        w.bits(3, 1); // two groups
+        w.bits(5, 0); // sectors of 5 bytes
+        let cl_lengths = [5; 22];
+        w.bits(4, 15); // all 22 code length codes
+        for &len in cl_lengths.iter() {
+            w.bits(4, len);
+        }
+        w.mtf_1_2(&cl_lengths, &mut CLEN_MTF_INIT.to_vec(), 256, &group_lengths);
+        let sel_lengths = [2, 2, 2];
+        for &len in sel_lengths.iter() {
+            w.bits(3, len);
+        }
+        w.mtf_1_2(&sel_lengths, &mut vec![0, 1], 0, &selectors);
+        for (sector, &group) in expected.chunks(5).zip(selectors.iter()) {
+            let lengths : Vec<usize> = group_lengths[group as usize * 256..][..256].iter().map(|&l| l as usize).collect();
+            for &byte in sector {
+                w.symbol(&lengths, byte as usize);
+            }
+        }
+
+        let mut output = vec![0u8; expected.len()];
+        decode(&w.bytes, &mut output).unwrap();
+        assert_eq!(output, expected);
+        assert!(decode(&w.bytes[..w.bytes.len() - 2], &mut output).is_err());
+    }
+}
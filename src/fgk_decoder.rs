@@ -0,0 +1,295 @@
+//! Decoder for xdelta3's FGK secondary compression (adaptive Huffman, compressor id 16).
+//!
+//! Encoder and decoder start from an empty tree and update it after every symbol. Symbols
+//! that weren't seen yet share one zero-weight leaf; reaching it is followed by the index of
+//! the symbol among the remaining unseen ones. The tree is kept across sections of the same
+//! kind, like the LZMA streams.
+
+use bit_reader::BitReader;
+use xdelta_error::XdeltaError;
+
+const ALPHABET_SIZE: usize = 256;
+const NONE: usize = usize::MAX;
+
+/// A node of the Huffman tree. Leaves are the first ALPHABET_SIZE nodes, indexed by symbol.
+/// While a symbol has weight 0, `left_child` and `right_child` link it into the list of
+/// unseen symbols instead.
+#[derive(Clone, Copy)]
+struct Node {
+    weight: u64,
+    parent: usize,
+    left_child: usize,
+    right_child: usize,
+    /// position in `order`
+    pos: usize,
+}
+
+pub struct FgkDecoder {
+    nodes: Vec<Node>,
+    /// all nodes of the tree ordered by weight, every node sits left of its parent
+    order: Vec<usize>,
+    root: usize,
+    /// head of the unseen symbol list, also the zero-weight leaf of the tree
+    remaining_zeros: usize,
+    zero_freq_count: usize,
+    zero_freq_exp: usize,
+    zero_freq_rem: usize,
+    decode_ptr: usize,
+    coded_bits: Vec<bool>,
+}
+
+impl FgkDecoder {
+    pub fn new() -> FgkDecoder {
+        let mut nodes = Vec::with_capacity(2 * ALPHABET_SIZE - 1);
+        for symbol in 0..ALPHABET_SIZE {
+            nodes.push(Node {
+                weight: 0,
+                parent: NONE,
+                left_child: if symbol > 0 { symbol - 1 } else { NONE },
+                right_child: if symbol + 1 < ALPHABET_SIZE { symbol + 1 } else { NONE },
+                pos: 0,
+            });
+        }
+        let mut decoder = FgkDecoder {
+            nodes,
+            order: vec![0],
+            root: 0,
+            remaining_zeros: 0,
+            zero_freq_count: ALPHABET_SIZE + 1,
+            zero_freq_exp: 0,
+            zero_freq_rem: 0,
+            decode_ptr: 0,
+            coded_bits: Vec::new(),
+        };
+        decoder.factor_remaining();
+        decoder
+    }
+
+    /// Decompresses one FGK coded section, filling all of `output`
+    pub fn decode(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), XdeltaError> {
+        let mut bits = BitReader::new(input);
+        for byte in output.iter_mut() {
+            while !self.decode_bit(bits.bit()?) {}
+            *byte = self.decode_data()?;
+        }
+        Ok(())
+    }
+
+    /// Counts down the unseen symbols and computes how many bits code an index among them
+    fn factor_remaining(&mut self) {
+        self.zero_freq_count -= 1;
+        let mut i = self.zero_freq_count;
+        self.zero_freq_exp = 0;
+        while i > 1 {
+            self.zero_freq_exp += 1;
+            i >>= 1;
+        }
+        self.zero_freq_rem = self.zero_freq_count - (1 << self.zero_freq_exp);
+    }
+
+    /// Consumes one bit, returns true once a complete symbol has been read
+    fn decode_bit(&mut self, bit: bool) -> bool {
+        if self.nodes[self.decode_ptr].weight == 0 {
+            if self.zero_freq_count == 1 {
+                return true;
+            }
+            let bits_required = if self.zero_freq_rem == 0 { self.zero_freq_exp } else { self.zero_freq_exp + 1 };
+            self.coded_bits.push(bit);
+            return self.coded_bits.len() >= bits_required;
+        }
+
+        let node = self.nodes[self.decode_ptr];
+        self.decode_ptr = if bit { node.right_child } else { node.left_child };
+        let node = self.nodes[self.decode_ptr];
+        if node.left_child == NONE {
+            node.weight != 0 || self.zero_freq_count == 1
+        } else {
+            false
+        }
+    }
+
+    fn decode_data(&mut self) -> Result<u8, XdeltaError> {
+        let mut symbol = self.decode_ptr;
+        if self.nodes[symbol].weight == 0 {
+            let n = self.coded_bits.iter().fold(0, |n, &bit| (n << 1) | bit as usize);
+            symbol = self.nth_zero(n)?;
+        }
+        self.coded_bits.clear();
+        self.update_tree(symbol);
+        self.decode_ptr = self.root;
+        Ok(symbol as u8)
+    }
+
+    fn nth_zero(&self, n: usize) -> Result<usize, XdeltaError> {
+        let mut node = self.remaining_zeros;
+        for _ in 0..n {
+            node = self.nodes[node].right_child;
+            if node == NONE {
+                return Err(XdeltaError::InvalidSecondaryData("secondary decoder invalid zero index"));
+            }
+        }
+        Ok(node)
+    }
+
+    fn update_tree(&mut self, symbol: usize) {
+        let mut node = if self.nodes[symbol].weight == 0 {
+            self.increase_zero_weight(symbol)
+        } else {
+            symbol
+        };
+        while node != self.root {
+            self.move_right(node);
+            self.nodes[node].weight += 1;
+            node = self.nodes[node].parent;
+        }
+        self.nodes[self.root].weight += 1;
+    }
+
+    /// Gives a symbol that is seen for the first time its own leaf, next to the zero leaf
+    fn increase_zero_weight(&mut self, symbol: usize) -> usize {
+        if self.zero_freq_count == 1 {
+            // the last unseen symbol simply becomes a regular leaf
+            self.nodes[symbol].right_child = NONE;
+            self.remaining_zeros = NONE;
+            return symbol;
+        }
+
+        let zero_ptr = self.remaining_zeros;
+        let parent = self.nodes[zero_ptr].parent;
+        let internal = self.nodes.len();
+        self.nodes.push(Node { weight: 0, parent, left_child: NONE, right_child: symbol, pos: 0 });
+        if zero_ptr == self.root {
+            self.root = internal;
+        } else if self.nodes[parent].right_child == zero_ptr {
+            self.nodes[parent].right_child = internal;
+        } else {
+            self.nodes[parent].left_child = internal;
+        }
+
+        self.eliminate_zero(symbol);
+        let head = self.remaining_zeros;
+        self.nodes[internal].left_child = head;
+        self.nodes[head].parent = internal;
+        self.nodes[symbol].parent = internal;
+        self.nodes[symbol].left_child = NONE;
+        self.nodes[symbol].right_child = NONE;
+
+        // the zero leaf was the lowest node, it is replaced by: zero leaf, symbol, new parent
+        self.order.splice(0..1, [head, symbol, internal].iter().cloned());
+        for (pos, &node) in self.order.iter().enumerate() {
+            self.nodes[node].pos = pos;
+        }
+        symbol
+    }
+
+    /// Unlinks a symbol from the list of unseen symbols
+    fn eliminate_zero(&mut self, symbol: usize) {
+        self.factor_remaining();
+        let Node { left_child: previous, right_child: next, .. } = self.nodes[symbol];
+        if previous == NONE {
+            self.remaining_zeros = next;
+            self.nodes[next].left_child = NONE;
+        } else if next == NONE {
+            self.nodes[previous].right_child = NONE;
+        } else {
+            self.nodes[next].left_child = previous;
+            self.nodes[previous].right_child = next;
+        }
+    }
+
+    /// Swaps `node` with the highest ordered node of the same weight, unless that's its parent,
+    /// so that incrementing its weight keeps `order` sorted
+    fn move_right(&mut self, node: usize) {
+        let weight = self.nodes[node].weight;
+        let mut leader_pos = self.nodes[node].pos;
+        while leader_pos + 1 < self.order.len() && self.nodes[self.order[leader_pos + 1]].weight == weight {
+            leader_pos += 1;
+        }
+        let leader = self.order[leader_pos];
+        if leader == node || self.nodes[node].parent == leader || weight == 0 {
+            return;
+        }
+
+        let node_pos = self.nodes[node].pos;
+        self.order.swap(node_pos, leader_pos);
+        self.nodes[node].pos = leader_pos;
+        self.nodes[leader].pos = node_pos;
+
+        let node_parent = self.nodes[node].parent;
+        let leader_parent = self.nodes[leader].parent;
+        let node_is_right = self.nodes[node_parent].right_child == node;
+        let leader_is_right = self.nodes[leader_parent].right_child == leader;
+        if node_is_right {
+            self.nodes[node_parent].right_child = leader;
+        } else {
+            self.nodes[node_parent].left_child = leader;
+        }
+        if leader_is_right {
+            self.nodes[leader_parent].right_child = node;
+        } else {
+            self.nodes[leader_parent].left_child = node;
+        }
+        self.nodes[node].parent = leader_parent;
+        self.nodes[leader].parent = node_parent;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FgkDecoder;
+
+    /// Codes `symbol` the way xdelta3's encoder does, using a decoder's copy of the tree
+    fn encode_symbol(tree: &mut FgkDecoder, symbol: usize, bits: &mut Vec<bool>) {
+        let mut coded = Vec::new();
+        let mut node = symbol;
+        if tree.nodes[symbol].weight == 0 {
+            let mut index = 0;
+            let mut zero = tree.remaining_zeros;
+            while zero != symbol {
+                zero = tree.nodes[zero].right_child;
+                index += 1;
+            }
+            let count = if tree.zero_freq_rem == 0 { tree.zero_freq_exp } else { tree.zero_freq_exp + 1 };
+            for i in 0..count {
+                coded.push((index >> i) & 1 == 1);
+            }
+            node = tree.remaining_zeros;
+        }
+        while node != tree.root {
+            let parent = tree.nodes[node].parent;
+            coded.push(tree.nodes[parent].right_child == node);
+            node = parent;
+        }
+        tree.update_tree(symbol);
+        bits.extend(coded.iter().rev());
+    }
+
+    fn encode_section(tree: &mut FgkDecoder, data: &[u8]) -> Vec<u8> {
+        let mut bits = Vec::new();
+        for &byte in data {
+            encode_symbol(tree, byte as usize, &mut bits);
+        }
+        bits.chunks(8).map(|chunk| chunk.iter().enumerate().fold(0u8, |byte, (i, &bit)| byte | (bit as u8) << i)).collect()
+    }
+
+    #[test]
+    fn round_trip_across_sections() {
+        let first : Vec<u8> = b"abracadabra, abracadabra!".iter().cloned().chain((0..=255u8).rev()).collect();
+        let second = b"the tree keeps adapting in the next section".to_vec();
+
+        let mut encoder = FgkDecoder::new();
+        let first_coded = encode_section(&mut encoder, &first);
+        let second_coded = encode_section(&mut encoder, &second);
+
+        let mut decoder = FgkDecoder::new();
+        let mut output = vec![0u8; first.len()];
+        decoder.decode(&first_coded, &mut output).unwrap();
+        assert_eq!(output, first);
+        let mut output = vec![0u8; second.len()];
+        decoder.decode(&second_coded, &mut output).unwrap();
+        assert_eq!(output, second);
+
+        let mut output = vec![0u8; first.len()];
+        assert!(FgkDecoder::new().decode(&first_coded[..first_coded.len() / 2], &mut output).is_err());
+    }
+}
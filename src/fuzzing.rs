@@ -0,0 +1,143 @@
+//! Entry points of the fuzz targets in fuzz/, available with the `fuzzing` feature.
+//!
+//! Each one feeds arbitrary bytes to one of the parsers. Errors are the expected outcome for
+//! most inputs, a panic is a bug. Inputs that once panicked are kept in fuzz/regressions and
+//! replayed by the tests below.
+
+use reader::Reader;
+use vcdiff_address_cache::AddressCache;
+use vcdiff_code_table::CodeTable;
+use vcdiff_header::Header;
+use vcdiff_target::SeekTarget;
+use vcdiff_window::Window;
+use decode_options::{DecodeLimits, DecodeOptions};
+use patch_inspector::PatchInspector;
+use patch_reader::decode_code_table;
+use {decode_base7_int, decode_with_options, encode_base7_int};
+
+use std::io::Cursor;
+
+/// Small enough that no input makes the fuzzer allocate much or run for long
+const LIMITS: DecodeLimits = DecodeLimits { max_window_size: 1 << 20, max_target_size: 1 << 22, max_app_header: 1 << 16 };
+
+fn options() -> DecodeOptions {
+  DecodeOptions { limits: LIMITS, ..DecodeOptions::default() }
+}
+
+/// `Header::new` on the input
+pub fn header(data: &[u8]) {
+  let _ = Header::new(&mut Reader::with_capacity(200, data), &LIMITS);
+}
+
+/// Parses windows with `Window::new` until one fails and applies each to a source of 256 bytes
+pub fn window(data: &[u8]) {
+  let source : Vec<u8> = (0..=255).collect();
+  let mut bytes = Reader::with_capacity(200, data);
+  let mut target = Cursor::new(Vec::new());
+  let mut target = match SeekTarget::new(&mut target) {
+    Ok(target) => target,
+    Err(_) => return,
+  };
+  let code_table = CodeTable::default();
+  let options = options();
+  let mut index = 0;
+  while let Ok(window) = Window::new(&mut bytes, &LIMITS) {
+    if window.decode_window(index, &mut Some(&source[..]), &mut target, &code_table, &options).is_err() {
+      return;
+    }
+    index += 1;
+  }
+}
+
+/// The first two bytes size the caches, then every address is a mode byte, the varint
+/// `here` and the encoded address
+pub fn address_cache(data: &[u8]) {
+  if data.len() < 2 {
+    return;
+  }
+  let mut cache = AddressCache::new(data[0] as usize % 16, data[1] as usize % 8);
+  let mut input = &data[2..];
+  while let Some((&mode, rest)) = input.split_first() {
+    let mut iter = rest.iter();
+    let here = match decode_base7_int(&mut iter) {
+      Ok(here) => here.result,
+      Err(_) => return,
+    };
+    match cache.decode(here, mode, iter.as_slice()) {
+      Ok((rest, _)) => input = rest,
+      Err(_) => return,
+    }
+  }
+}
+
+/// Both varint decoders on the input, a decoded value has to encode back to the same bytes
+pub fn base7_int(data: &[u8]) {
+  let decoded = decode_base7_int(&mut data.iter());
+  let read = Reader::with_capacity(1, data).decode_base7_int();
+  match decoded {
+    Ok(value) => {
+      assert_eq!(read.ok(), Some(value.result));
+      // leading zero groups are valid but encode to fewer bytes
+      if data[0] != 0x80 {
+        let mut encoded = Vec::new();
+        encode_base7_int(value.result, &mut encoded);
+        assert_eq!(&encoded[..], &data[..value.bytes_read]);
+      }
+    },
+    Err(_) => assert!(read.is_err()),
+  }
+}
+
+/// An application-defined code table: the first two bytes are the near and same cache sizes,
+/// the rest is the delta against the default code table
+pub fn code_table(data: &[u8]) {
+  if data.len() < 2 {
+    return;
+  }
+  if let Ok(table) = decode_code_table(&data[2..], &LIMITS) {
+    let _ = CodeTable::from_bytes(data[0], data[1], &table);
+  }
+}
+
+/// A whole patch: the first byte is the length of the source that follows, the rest is the
+/// patch. It is decoded and its instructions are listed.
+pub fn decode(data: &[u8]) {
+  let (&source_length, rest) = match data.split_first() {
+    Some(split) => split,
+    None => return,
+  };
+  let (source, patch) = rest.split_at((source_length as usize).min(rest.len()));
+  let _ = decode_with_options(Some(Cursor::new(source)), patch, Cursor::new(Vec::new()), &options());
+  if let Ok(inspector) = PatchInspector::with_options(patch, &options()) {
+    for window in inspector {
+      match window {
+        Ok(window) => window.instructions().take_while(Result::is_ok).for_each(drop),
+        Err(_) => break,
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+  use std::path::PathBuf;
+
+  /// Runs every input kept for the fuzz target `target`
+  fn replay(target: &str, run: fn(&[u8])) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fuzz").join("regressions").join(target);
+    for entry in fs::read_dir(dir).unwrap() {
+      run(&fs::read(entry.unwrap().path()).unwrap());
+    }
+  }
+
+  #[test]
+  fn regressions() {
+    replay("header", super::header);
+    replay("window", super::window);
+    replay("address_cache", super::address_cache);
+    replay("base7_int", super::base7_int);
+    replay("code_table", super::code_table);
+    replay("decode", super::decode);
+  }
+}
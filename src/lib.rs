@@ -1,110 +1,884 @@
+#[cfg(all(feature = "lzma-sys", not(feature = "pure-rust-lzma")))]
 extern crate lzma_sys;
+#[cfg(any(test, feature = "pure-rust-lzma"))]
+extern crate lzma_rust2;
+#[cfg(any(test, feature = "async"))]
+extern crate tokio;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+#[cfg(test)]
+extern crate sha2;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
 
 mod vcdiff_header;
+mod app_header;
 mod vcdiff_window;
 mod vcdiff_address_cache;
 mod vcdiff_code_table;
+mod vcdiff_rolling_hash;
+mod vcdiff_encoder;
+#[cfg(all(feature = "lzma-sys", not(feature = "pure-rust-lzma")))]
 mod lzma_action;
+#[cfg(all(feature = "lzma-sys", not(feature = "pure-rust-lzma")))]
 mod lzma_stream_wrapper;
 mod lzma_error;
 mod reader;
+mod bit_reader;
+mod djw_decoder;
+mod fgk_decoder;
+mod secondary_decompressor;
+mod vcdiff_instructions;
+mod patch_reader;
+mod patch_inspector;
+mod checkpoint;
+mod progress;
+mod decode_summary;
+mod adler32;
+mod decode_options;
+mod vcdiff_source;
+mod vcdiff_target;
+mod xdelta_error;
+#[cfg(feature = "async")]
+mod async_decode;
+#[cfg(feature = "parallel")]
+mod parallel_decode;
+#[cfg(any(test, feature = "fuzzing"))]
+#[doc(hidden)]
+pub mod fuzzing;
 
-use vcdiff_header::Header;
-use vcdiff_window::Window;
+use patch_reader::PatchReader;
 
+#[cfg(all(feature = "lzma-sys", not(feature = "pure-rust-lzma")))]
 use lzma_sys::{lzma_ret, lzma_end, lzma_code, lzma_auto_decoder, lzma_stream};
-use lzma_action::LzmaAction;
-use lzma_stream_wrapper::LzmaStreamWrapper;
-use reader::Reader;
+use vcdiff_source::{Source, SeekSource, FileSource};
+use vcdiff_target::{Target, SeekTarget, HistoryTarget};
+use decode_summary::HashingTarget;
 
-use std::fs::OpenOptions;
+pub use vcdiff_encoder::encode;
+pub use decode_options::{DecodeOptions, DecodeLimits};
+pub use vcdiff_header::{is_vcdiff, Header, CodeTable as CustomCodeTable};
+pub use app_header::AppHeader;
+pub use vcdiff_instructions::{Instructions, Operation};
+pub use patch_inspector::{PatchInspector, WindowInfo};
+pub use checkpoint::Checkpoint;
+pub use progress::{Progress, ProgressObserver};
+pub use decode_summary::{DecodeSummary, TargetHasher};
+pub use adler32::Adler32;
+pub use secondary_decompressor::{SecondaryDecompressor, SecondaryFactory, SecondaryRegistry, DJW_ID, LZMA_ID, FGK_ID};
+pub use lzma_error::LzmaError;
+pub use xdelta_error::XdeltaError;
+#[cfg(feature = "async")]
+pub use async_decode::{decode_async, decode_async_with_options, DecodeAsync};
+#[cfg(feature = "parallel")]
+pub use parallel_decode::{decode_parallel, decode_file_parallel};
+
+use std::fs::{File, OpenOptions};
+use std::io::{Cursor, Read, Write, Seek};
+use std::ops::ControlFlow;
 use std::path::Path;
 
-pub fn decode_file<P: AsRef<Path>>(source_file_path: Option<P>, patch_file_path: P, target_file_path: P) {
-  let mut source = match source_file_path {
-    Some(path) => Some(OpenOptions::new().read(true).open(path).unwrap()),
+pub fn decode_file<P: AsRef<Path>>(source_file_path: Option<P>, patch_file_path: P, target_file_path: P) -> Result<(), XdeltaError> {
+  let source = match source_file_path {
+    Some(path) => Some(FileSource::open(path)?),
     None => None
   };
-  let patch = OpenOptions::new().read(true).open(patch_file_path).unwrap();
-  let mut target = OpenOptions::new().read(true).write(true).create(true).open(target_file_path).unwrap();
-  let mut bytes = Reader::with_capacity(200,patch);
-
-  //read header
-  let header = Header::new(&mut bytes);
-
-  //initialize lzma-decompressor-streams
-  let mut data_stream = LzmaStreamWrapper::new();
-  data_stream.stream_decoder(std::u64::MAX, 0).unwrap();
-  let mut instructions_stream = LzmaStreamWrapper::new();
-  instructions_stream.stream_decoder(std::u64::MAX, 0).unwrap();
-  let mut addresses_stream = LzmaStreamWrapper::new();
-  addresses_stream.stream_decoder(std::u64::MAX, 0).unwrap();
-
-  //read windows
-  while bytes.peek().is_some() {
-    let mut window = Window::new(&mut bytes);
-
-    if header.secondary_compressor_id == Some(2) {
-      //decompress lzma2
-      if window.delta_indicator % 2 >= 1 {
-        //decompress data
-        let size = decode_base7_int(&mut window.data[0..10].iter());
-        let mut decoded_data : Vec<u8> = Vec::with_capacity(size.result.unwrap() as usize);
-        decoded_data.resize(size.result.unwrap() as usize,0);
-        let result = data_stream.code(&mut window.data[size.bytes_read..], &mut decoded_data, LzmaAction::LzmaRun); // 0 = LzmaRun
-        if result.ret.is_ok() {
-          window.data = decoded_data;
-          window.data_length = size.result.unwrap();
-        }
-      }
-      if window.delta_indicator % 4 >= 2 {
-        //decompress instructions
-        let size = decode_base7_int(&mut window.instructions[0..10].iter());
-        let mut decoded_instructions : Vec<u8> = Vec::with_capacity(size.result.unwrap() as usize);
-        decoded_instructions.resize(size.result.unwrap() as usize,0);
-        let result = instructions_stream.code(&mut window.instructions[size.bytes_read..], &mut decoded_instructions, LzmaAction::LzmaRun);
-        if result.ret.is_ok() {
-          window.instructions = decoded_instructions;
-          window.instructions_length = size.result.unwrap();
-        }
-      }
-      if window.delta_indicator % 8 >= 4 {
-        //decompress addresses
-        let size = decode_base7_int(&mut window.addresses[0..10].iter());
-        let mut decoded_addresses : Vec<u8> = Vec::with_capacity(size.result.unwrap() as usize);
-        decoded_addresses.resize(size.result.unwrap() as usize,0);
-        let result = addresses_stream.code(&mut window.addresses[size.bytes_read..], &mut decoded_addresses, LzmaAction::LzmaRun);
-        if result.ret.is_ok() {
-          window.addresses = decoded_addresses;
-          window.addresses_length = size.result.unwrap();
-        }
-      }
-      window.delta_indicator = 0;
+  let patch = OpenOptions::new().read(true).open(patch_file_path)?;
+  let mut target = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(target_file_path)?;
+  let result = decode_windows(source, patch, SeekTarget::new(&mut target)?, &DecodeOptions::default());
+  truncate_corrupt_window(result, &mut target)
+}
+
+/**
+* Same as `decode_file`, reporting the progress to `observer` after every window.
+* The patch total is the length of the patch file.
+*/
+pub fn decode_file_with_progress<P: AsRef<Path>, O: ProgressObserver>(source_file_path: Option<P>, patch_file_path: P, target_file_path: P, observer: O) -> Result<(), XdeltaError> {
+  let source = match source_file_path {
+    Some(path) => Some(FileSource::open(path)?),
+    None => None
+  };
+  let patch = OpenOptions::new().read(true).open(patch_file_path)?;
+  let patch_total = patch.metadata()?.len();
+  let mut target = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(target_file_path)?;
+  let result = report_progress(source, patch, SeekTarget::new(&mut target)?, &DecodeOptions::default(), Some(patch_total), observer);
+  truncate_corrupt_window(result, &mut target)
+}
+
+/**
+* Same as `decode_with_options`, reporting the progress to `observer` after every window.
+* `patch_total` is passed on as `Progress::patch_total`.
+*/
+pub fn decode_with_progress<S, P, T, O>(source: Option<S>, patch: P, target: T, options: &DecodeOptions, patch_total: Option<u64>, observer: O) -> Result<(), XdeltaError>
+  where S: Read + Seek, P: Read, T: Read + Write + Seek, O: ProgressObserver {
+  report_progress(source.map(SeekSource), patch, SeekTarget::new(target)?, options, patch_total, observer)
+}
+
+fn report_progress<S, P, T, O>(source: Option<S>, patch: P, target: T, options: &DecodeOptions, patch_total: Option<u64>, mut observer: O) -> Result<(), XdeltaError>
+  where S: Source, P: Read, T: Target, O: ProgressObserver {
+  let windows = PatchReader::new(patch, &options.secondary, &options.limits)?;
+  apply_windows(windows, 0, source, target, options, patch_total, |progress| observer.window_done(progress))
+}
+
+/**
+* Same as `decode_file`, hashing the target with `hasher` while it is written. The summary holds
+* the digest and the number of windows and bytes, e.g. `Adler32::new()` for the whole target's
+* Adler-32.
+*/
+pub fn decode_file_with_summary<P: AsRef<Path>, H: TargetHasher>(source_file_path: Option<P>, patch_file_path: P, target_file_path: P, hasher: H) -> Result<DecodeSummary, XdeltaError> {
+  let source = match source_file_path {
+    Some(path) => Some(FileSource::open(path)?),
+    None => None
+  };
+  let patch = OpenOptions::new().read(true).open(patch_file_path)?;
+  let mut target = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(target_file_path)?;
+  let result = summarize(source, patch, SeekTarget::new(&mut target)?, &DecodeOptions::default(), hasher);
+  truncate_corrupt_window(result, &mut target)
+}
+
+/**
+* Same as `decode_with_options`, hashing the target with `hasher` while it is written.
+*/
+pub fn decode_with_summary<S, P, T, H>(source: Option<S>, patch: P, target: T, options: &DecodeOptions, hasher: H) -> Result<DecodeSummary, XdeltaError>
+  where S: Read + Seek, P: Read, T: Read + Write + Seek, H: TargetHasher {
+  summarize(source.map(SeekSource), patch, SeekTarget::new(target)?, options, hasher)
+}
+
+fn summarize<S, P, T, H>(source: Option<S>, patch: P, target: T, options: &DecodeOptions, hasher: H) -> Result<DecodeSummary, XdeltaError>
+  where S: Source, P: Read, T: Target, H: TargetHasher {
+  let windows = PatchReader::new(patch, &options.secondary, &options.limits)?;
+  // a patch without windows is only its header
+  let mut last = Progress { window: 0, patch_bytes: windows.offset(), patch_total: None, target_bytes: 0 };
+  let mut applied = 0;
+  let mut target = HashingTarget { inner: target, hasher };
+  apply_windows(windows, 0, source, &mut target, options, None, |progress| {
+    applied = progress.window + 1;
+    last = *progress;
+    ControlFlow::Continue(())
+  })?;
+  Ok(DecodeSummary {
+    windows: applied,
+    patch_bytes: last.patch_bytes,
+    target_bytes: last.target_bytes,
+    digest: target.hasher.finish(),
+  })
+}
+
+/**
+* Applies the VCDIFF/xdelta3 patch read from `patch` to `source` and writes the result to `target`.
+* `target` has to be readable and seekable as well, since VCD_TARGET windows copy from earlier output.
+*/
+pub fn decode<S: Read + Seek, P: Read, T: Read + Write + Seek>(source: Option<S>, patch: P, target: T) -> Result<(), XdeltaError> {
+  decode_with_options(source, patch, target, &DecodeOptions::default())
+}
+
+/**
+* Same as `decode`, but lets the caller e.g. skip checksum verification.
+*/
+pub fn decode_with_options<S: Read + Seek, P: Read, T: Read + Write + Seek>(source: Option<S>, patch: P, target: T, options: &DecodeOptions) -> Result<(), XdeltaError> {
+  decode_windows(source.map(SeekSource), patch, SeekTarget::new(target)?, options)
+}
+
+/**
+* Same as `decode_with_options`, for targets that can't be read back like standard output or a socket.
+* The output is retained in memory for VCD_TARGET windows, see `DecodeOptions::target_history`.
+*/
+pub fn decode_to_writer<S: Read + Seek, P: Read, W: Write>(source: Option<S>, patch: P, target: W, options: &DecodeOptions) -> Result<(), XdeltaError> {
+  decode_windows(source.map(SeekSource), patch, HistoryTarget::new(target, options.target_history), options)
+}
+
+/**
+* Applies an in-memory VCDIFF/xdelta3 patch to an in-memory source and returns the reconstructed target.
+* COPY instructions read straight from the `source` slice.
+*/
+pub fn decode_to_vec(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, XdeltaError> {
+  let mut target = Cursor::new(Vec::new());
+  decode_windows(Some(source), patch, SeekTarget::new(&mut target)?, &DecodeOptions::default())?;
+  Ok(target.into_inner())
+}
+
+/**
+* Same as `decode_file`, but reports a `Checkpoint` after every window and can continue an
+* interrupted decoding from one. Save the checkpoints with `Checkpoint::to_bytes`.
+*/
+pub fn decode_file_resumable<P: AsRef<Path>, F: FnMut(&Checkpoint)>(source_file_path: Option<P>, patch_file_path: P, target_file_path: P, resume_from: Option<&Checkpoint>, on_checkpoint: F) -> Result<(), XdeltaError> {
+  let source = match source_file_path {
+    Some(path) => Some(FileSource::open(path)?),
+    None => None
+  };
+  let patch = OpenOptions::new().read(true).open(patch_file_path)?;
+  let mut target = OpenOptions::new().read(true).write(true).create(true).truncate(resume_from.is_none()).open(target_file_path)?;
+  let result = resume_windows(source, patch, &mut target, &DecodeOptions::default(), resume_from, on_checkpoint);
+  truncate_corrupt_window(result, &mut target)?;
+  // the interrupted run may have written past the end of the last window
+  let length = target.stream_position()?;
+  target.set_len(length)?;
+  Ok(())
+}
+
+/**
+* Same as `decode_with_options`, calling `on_checkpoint` after every window. With `resume_from`,
+* the windows up to the checkpoint are skipped and `target` has to hold their output already.
+* `patch` and `target` have to be at the position the interrupted decoding started at.
+*/
+pub fn decode_resumable<S, P, T, F>(source: Option<S>, patch: P, target: T, options: &DecodeOptions, resume_from: Option<&Checkpoint>, on_checkpoint: F) -> Result<(), XdeltaError>
+  where S: Read + Seek, P: Read + Seek, T: Read + Write + Seek, F: FnMut(&Checkpoint) {
+  resume_windows(source.map(SeekSource), patch, target, options, resume_from, on_checkpoint)
+}
+
+fn resume_windows<S, P, T, F>(source: Option<S>, patch: P, target: T, options: &DecodeOptions, resume_from: Option<&Checkpoint>, mut on_checkpoint: F) -> Result<(), XdeltaError>
+  where S: Source, P: Read + Seek, T: Read + Write + Seek, F: FnMut(&Checkpoint) {
+  let mut windows = PatchReader::new(patch, &options.secondary, &options.limits)?;
+  let patch_length = windows.length()?;
+  let (target, first_window) = match resume_from {
+    Some(checkpoint) => {
+      windows.resume(checkpoint)?;
+      (SeekTarget::resume(target, checkpoint.target_offset)?, checkpoint.windows)
+    },
+    None => (SeekTarget::new(target)?, 0),
+  };
+  apply_windows(windows, first_window, source, target, options, Some(patch_length), |progress| {
+    on_checkpoint(&Checkpoint {
+      windows: progress.window + 1,
+      patch_offset: progress.patch_bytes,
+      patch_length,
+      target_offset: progress.target_bytes,
+    });
+    ControlFlow::Continue(())
+  })
+}
+
+/**
+* Cuts a target file back to the start of a window that failed its checksum, which is where the
+* target stands then. Streamed chunks of that window may have been written already.
+*/
+fn truncate_corrupt_window<R>(result: Result<R, XdeltaError>, target: &mut File) -> Result<R, XdeltaError> {
+  if let Err(XdeltaError::ChecksumMismatch { .. }) = result {
+    let length = target.stream_position()?;
+    target.set_len(length)?;
+  }
+  result
+}
+
+fn decode_windows<S: Source, P: Read, T: Target>(source: Option<S>, patch: P, target: T, options: &DecodeOptions) -> Result<(), XdeltaError> {
+  let windows = PatchReader::new(patch, &options.secondary, &options.limits)?;
+  apply_windows(windows, 0, source, target, options, None, |_| ControlFlow::Continue(()))
+}
+
+/// Decodes the remaining windows, `after_window` can stop the decoding after each of them
+fn apply_windows<S, P, T, F>(mut windows: PatchReader<P>, first_window: u64, mut source: Option<S>, mut target: T, options: &DecodeOptions, patch_total: Option<u64>, mut after_window: F) -> Result<(), XdeltaError>
+  where S: Source, P: Read, T: Target, F: FnMut(&Progress) -> ControlFlow<()> {
+  let code_table = windows.code_table.clone();
+  let mut index = first_window;
+  while let Some(window) = windows.next() {
+    window?.decode_window(index, &mut source, &mut target, &code_table, options)?;
+    let progress = Progress { window: index, patch_bytes: windows.offset(), patch_total, target_bytes: target.position() };
+    index += 1;
+    if after_window(&progress).is_break() {
+      return Err(XdeltaError::Cancelled { windows: index });
     }
-    window.decode_window(&mut source, &mut target).unwrap();
   }
+  Ok(())
 }
 
 #[derive(Debug)]
 pub struct DecodeResult {
-  result: Option<u64>,
-  bytes_read: usize,
+  pub result: u64,
+  pub bytes_read: usize,
 }
 
-pub fn decode_base7_int(bytes: &mut std::slice::Iter<'_, u8>) -> DecodeResult {
+pub fn decode_base7_int(bytes: &mut std::slice::Iter<'_, u8>) -> Result<DecodeResult, XdeltaError> {
   let mut result : u64 = 0;
   let mut not_finished : bool = true;
   let mut counter = 0;
   while not_finished {
     if counter == 10 {
-      return DecodeResult { result: None, bytes_read: counter };
+      return Err(XdeltaError::TruncatedVarint);
     }
     counter += 1;
-    let next_byte : u64 = bytes.next().unwrap().clone() as u64;
+    let next_byte : u64 = match bytes.next() {
+      Some(&byte) => byte as u64,
+      None => return Err(XdeltaError::TruncatedVarint),
+    };
+    if result >> 57 != 0 {
+      return Err(XdeltaError::TruncatedVarint); // doesn't fit in 64 bits
+    }
     result = (result << 7) | (next_byte & 127);
     if (next_byte & 128) == 0 {
       not_finished = false;
     }
   }
-  return DecodeResult { result: Some(result), bytes_read: counter };
+  Ok(DecodeResult { result, bytes_read: counter })
+}
+
+pub fn encode_base7_int(value: u64, output: &mut Vec<u8>) {
+  let mut groups = [0u8; 10];
+  let mut count = 0;
+  let mut remaining = value;
+  loop {
+    groups[count] = (remaining & 127) as u8;
+    count += 1;
+    remaining >>= 7;
+    if remaining == 0 {
+      break;
+    }
+  }
+  for idx in (0..count).rev() {
+    let continuation = if idx > 0 { 128 } else { 0 };
+    output.push(groups[idx] | continuation);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{decode, decode_file, decode_file_resumable, decode_file_with_progress, decode_resumable, decode_with_progress, decode_to_vec, decode_to_writer, decode_with_options, decode_with_summary, decode_file_with_summary, encode, encode_base7_int, is_vcdiff, Adler32, Checkpoint, DecodeLimits, DecodeOptions, Progress, TargetHasher, SecondaryDecompressor, XdeltaError};
+  use std::ops::ControlFlow;
+  use vcdiff_code_table::CodeTable;
+  use std::fs;
+  use std::io::Cursor;
+  use std::path::PathBuf;
+
+  /// A directory of its own for the files of one test, removed with them when it's dropped
+  pub struct TempDir(PathBuf);
+
+  impl TempDir {
+    pub fn new(test: &str) -> TempDir {
+      let path = std::env::temp_dir().join(format!("xdelta-{}-{}", test, std::process::id()));
+      let _ = fs::remove_dir_all(&path);
+      fs::create_dir_all(&path).unwrap();
+      TempDir(path)
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+      self.0.join(name)
+    }
+  }
+
+  impl Drop for TempDir {
+    fn drop(&mut self) {
+      let _ = fs::remove_dir_all(&self.0);
+    }
+  }
+
+  #[test]
+  fn missing_patch_is_io_error() {
+    let dir = TempDir::new("missing");
+    let result = decode_file(None, dir.join("patch.vcdiff"), dir.join("out"));
+    assert!(matches!(result, Err(XdeltaError::Io(_))));
+  }
+
+  #[test]
+  fn add_only_window() {
+    let dir = TempDir::new("add-only");
+    let patch = dir.join("patch.vcdiff");
+    let target = dir.join("out");
+    fs::write(&patch, ADD_ONLY_PATCH).unwrap();
+    decode_file(None, patch, target.clone()).unwrap();
+    assert_eq!(fs::read(target).unwrap(), b"abc");
+  }
+
+  /// Stand-in for an in-house codec: every byte is inverted
+  struct Inverted;
+
+  impl SecondaryDecompressor for Inverted {
+    fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), XdeltaError> {
+      for (out, byte) in output.iter_mut().zip(input) {
+        *out = !byte;
+      }
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn registered_secondary_decompressor() {
+    let patch : &[u8] = &[0xD6, 0xC3, 0xC4, 0x00, 0x01, 42,
+                          0x00, 0x0A, 0x03, 0x01, 0x04, 0x01, 0x00, 0x03, !b'a', !b'b', !b'c', 0x04];
+    let mut target = Cursor::new(Vec::new());
+    let mut options = DecodeOptions::default();
+    let result = decode_with_options(None::<Cursor<Vec<u8>>>, patch, &mut target, &options);
+    assert!(matches!(result, Err(XdeltaError::UnsupportedSecondaryCompressor(42))));
+
+    options.secondary.register(42, || Ok(Box::new(Inverted)));
+    decode_with_options(None::<Cursor<Vec<u8>>>, patch, &mut target, &options).unwrap();
+    assert_eq!(target.into_inner(), b"abc");
+  }
+
+  #[test]
+  fn source_copy_in_memory() {
+    let source = Cursor::new(b"hello world".to_vec());
+    let patch : &[u8] = &[0xD6, 0xC3, 0xC4, 0x00, 0x00,
+                          0x01, 0x0B, 0x00, 0x07, 0x05, 0x00, 0x00, 0x01, 0x01, 0x15, 0x00];
+    let mut target = Cursor::new(Vec::new());
+    decode(Some(source), patch, &mut target).unwrap();
+    assert_eq!(target.into_inner(), b"hello");
+  }
+
+  #[test]
+  fn source_copy_from_slice() {
+    let patch = [0xD6, 0xC3, 0xC4, 0x00, 0x00,
+                 0x01, 0x0B, 0x00, 0x07, 0x05, 0x00, 0x00, 0x01, 0x01, 0x15, 0x00];
+    assert_eq!(decode_to_vec(b"hello world", &patch).unwrap(), b"hello");
+    assert!(matches!(decode_to_vec(b"hi", &patch), Err(XdeltaError::InvalidWindow(_))));
+  }
+
+  #[test]
+  fn encode_round_trip_through_files() {
+    let dir = TempDir::new("encode");
+    let source : Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let mut target = source[1_000..15_000].to_vec();
+    target.extend_from_slice(b"some bytes that are not in the source");
+    fs::write(dir.join("source"), &source).unwrap();
+    fs::write(dir.join("patch.vcdiff"), encode(&source, &target)).unwrap();
+    decode_file(Some(dir.join("source")), dir.join("patch.vcdiff"), dir.join("out")).unwrap();
+    assert_eq!(fs::read(dir.join("out")).unwrap(), target);
+  }
+
+  #[test]
+  fn checksum_mismatch() {
+    let mut patch = encode(&[], b"0123456789");
+    let last_data_byte = patch.len() - 2;
+    patch[last_data_byte] = b'X';
+    match decode_to_vec(&[], &patch) {
+      Err(XdeltaError::ChecksumMismatch { window: 0, expected, actual }) => assert_ne!(expected, actual),
+      other => panic!("expected a checksum mismatch, got {:?}", other),
+    }
+
+    let options = DecodeOptions { verify_checksums: false, ..DecodeOptions::default() };
+    let mut target = Cursor::new(Vec::new());
+    decode_with_options(None::<Cursor<Vec<u8>>>, &patch[..], &mut target, &options).unwrap();
+    assert_eq!(target.into_inner(), b"012345678X");
+
+    // the streamed chunks are written, but the target goes back to the start of the window
+    let options = DecodeOptions { stream_chunk_size: Some(3), ..DecodeOptions::default() };
+    let mut target = Cursor::new(b"prefix".to_vec());
+    target.set_position(6);
+    let result = decode_with_options(None::<Cursor<Vec<u8>>>, &patch[..], &mut target, &options);
+    assert!(matches!(result, Err(XdeltaError::ChecksumMismatch { window: 0, .. })));
+    assert_eq!(target.position(), 6);
+    assert_eq!(target.get_ref(), b"prefix012345678");
+  }
+
+  #[test]
+  fn streamed_window_output() {
+    let source = b"the quick brown fox jumps over the lazy dog".to_vec();
+    let mut target = b"jumps over the quick brown fox, ".to_vec();
+    for round in 0..40u8 {
+      target.extend_from_slice(b"a line that repeats with a counter ");
+      target.push(round);
+    }
+    target.extend_from_slice(&[b'z'; 300]);
+    let patch = encode(&source, &target);
+
+    for &chunk_size in &[1, 7, 64, 4096] {
+      let options = DecodeOptions { stream_chunk_size: Some(chunk_size), ..DecodeOptions::default() };
+      let mut output = Cursor::new(Vec::new());
+      decode_with_options(Some(Cursor::new(&source)), &patch[..], &mut output, &options).unwrap();
+      assert_eq!(output.into_inner(), target);
+    }
+  }
+
+  /// One window without a source segment, ADD "abc"
+  pub const ADD_ONLY_PATCH: [u8; 16] = [
+    0xD6, 0xC3, 0xC4, 0x00, 0x00,
+    0x00, 0x09, 0x03, 0x00, 0x03, 0x01, 0x00, b'a', b'b', b'c', 0x04,
+  ];
+
+  /// Three windows of self-similar data, the later two copy from the target the earlier ones produced
+  pub const VCD_TARGET_PATCH: [u8; 49] = [
+    0xD6, 0xC3, 0xC4, 0x00, 0x00,
+    // ADD "abcdefgh"
+    0x00, 0x0E, 0x08, 0x00, 0x08, 0x01, 0x00, b'a', b'b', b'c', b'd', b'e', b'f', b'g', b'h', 0x09,
+    // segment target[0..8]: COPY 8 from the segment, ADD "XY", COPY 10 from the start of the window
+    0x02, 0x08, 0x00, 0x0C, 0x14, 0x00, 0x02, 0x03, 0x02, b'X', b'Y', 0x18, 0x03, 0x1A, 0x00, 0x08,
+    // segment target[8..28]: COPY 20 from the segment
+    0x02, 0x14, 0x08, 0x08, 0x14, 0x00, 0x00, 0x02, 0x01, 0x13, 0x14, 0x00,
+  ];
+  pub const VCD_TARGET_OUTPUT: &[u8] = b"abcdefghabcdefghXYabcdefghXYabcdefghXYabcdefghXY";
+
+  #[test]
+  fn vcd_target_windows() {
+    for &chunk_size in &[None, Some(1), Some(3)] {
+      let options = DecodeOptions { stream_chunk_size: chunk_size, ..DecodeOptions::default() };
+      let mut output = Cursor::new(b"unrelated prefix".to_vec());
+      output.set_position(16);
+      decode_with_options(None::<Cursor<Vec<u8>>>, &VCD_TARGET_PATCH[..], &mut output, &options).unwrap();
+      assert_eq!(&output.get_ref()[16..], VCD_TARGET_OUTPUT);
+
+      let mut output = Vec::new();
+      decode_to_writer(None::<Cursor<Vec<u8>>>, &VCD_TARGET_PATCH[..], &mut output, &options).unwrap();
+      assert_eq!(output, VCD_TARGET_OUTPUT);
+    }
+  }
+
+  #[test]
+  fn vcd_target_history() {
+    // the last window copies from the 20 bytes the previous one produced
+    let options = DecodeOptions { target_history: Some(20), ..DecodeOptions::default() };
+    let mut output = Vec::new();
+    decode_to_writer(None::<Cursor<Vec<u8>>>, &VCD_TARGET_PATCH[..], &mut output, &options).unwrap();
+    assert_eq!(output, VCD_TARGET_OUTPUT);
+
+    let options = DecodeOptions { target_history: Some(19), ..DecodeOptions::default() };
+    let result = decode_to_writer(None::<Cursor<Vec<u8>>>, &VCD_TARGET_PATCH[..], Vec::new(), &options);
+    assert!(matches!(result, Err(XdeltaError::InvalidWindow(_))));
+
+    // by default only the largest window so far is kept, 20 bytes, which doesn't reach target[0..20]
+    let mut patch = VCD_TARGET_PATCH;
+    patch[39] = 0x00;
+    let result = decode_to_writer(None::<Cursor<Vec<u8>>>, &patch[..], Vec::new(), &DecodeOptions::default());
+    assert!(matches!(result, Err(XdeltaError::InvalidWindow("copy reaches before the retained target history"))), "{:?}", result);
+    let options = DecodeOptions { target_history: Some(usize::MAX), ..DecodeOptions::default() };
+    let mut output = Vec::new();
+    decode_to_writer(None::<Cursor<Vec<u8>>>, &patch[..], &mut output, &options).unwrap();
+    assert_eq!(output, [&VCD_TARGET_OUTPUT[..28], &VCD_TARGET_OUTPUT[..20]].concat());
+
+    // a segment can't overlap the window that is being decoded
+    let mut patch = VCD_TARGET_PATCH;
+    patch[23] = 0x01;
+    let result = decode(None::<Cursor<Vec<u8>>>, &patch[..], Cursor::new(Vec::new()));
+    assert!(matches!(result, Err(XdeltaError::InvalidWindow("VCD_TARGET segment reaches past the decoded target"))));
+  }
+
+  /// Hands out the patch up to `fail_at`, then fails once with `kind`
+  struct FailingReader {
+    patch: &'static [u8],
+    pos: usize,
+    fail_at: usize,
+    kind: std::io::ErrorKind,
+  }
+
+  impl std::io::Read for FailingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+      if self.pos == self.fail_at {
+        self.fail_at = usize::MAX;
+        return Err(self.kind.into());
+      }
+      let end = self.patch.len().min(self.fail_at).min(self.pos + buf.len());
+      let read = end - self.pos;
+      buf[..read].copy_from_slice(&self.patch[self.pos..end]);
+      self.pos = end;
+      Ok(read)
+    }
+  }
+
+  #[test]
+  fn read_error_between_windows() {
+    // the second window starts at byte 21
+    let patch = FailingReader { patch: &VCD_TARGET_PATCH, pos: 0, fail_at: 21, kind: std::io::ErrorKind::Other };
+    let result = decode_to_writer(None::<Cursor<Vec<u8>>>, patch, Vec::new(), &DecodeOptions::default());
+    assert!(matches!(result, Err(XdeltaError::Io(ref err)) if err.kind() == std::io::ErrorKind::Other), "{:?}", result);
+
+    let patch = FailingReader { patch: &VCD_TARGET_PATCH, pos: 0, fail_at: 21, kind: std::io::ErrorKind::Interrupted };
+    let mut output = Vec::new();
+    decode_to_writer(None::<Cursor<Vec<u8>>>, patch, &mut output, &DecodeOptions::default()).unwrap();
+    assert_eq!(output, VCD_TARGET_OUTPUT);
+  }
+
+  fn checkpoints(patch: &[u8], options: &DecodeOptions) -> (Vec<u8>, Vec<Checkpoint>) {
+    let mut checkpoints = Vec::new();
+    let mut target = Cursor::new(Vec::new());
+    decode_resumable(None::<Cursor<Vec<u8>>>, Cursor::new(patch), &mut target, options, None, |checkpoint| checkpoints.push(*checkpoint)).unwrap();
+    (target.into_inner(), checkpoints)
+  }
+
+  #[test]
+  fn resume_from_checkpoint() {
+    let options = DecodeOptions::default();
+    let (output, checkpoints) = checkpoints(&VCD_TARGET_PATCH, &options);
+    assert_eq!(output, VCD_TARGET_OUTPUT);
+    assert_eq!(checkpoints.len(), 3);
+    assert_eq!(checkpoints[0], Checkpoint { windows: 1, patch_offset: 21, patch_length: 49, target_offset: 8 });
+
+    // the interrupted run got halfway through the second window
+    let mut target = Cursor::new(VCD_TARGET_OUTPUT[..18].to_vec());
+    target.get_mut().extend_from_slice(b"garbage");
+    let mut resumed = Vec::new();
+    decode_resumable(None::<Cursor<Vec<u8>>>, Cursor::new(&VCD_TARGET_PATCH[..]), &mut target, &options, Some(&checkpoints[0]), |checkpoint| resumed.push(*checkpoint)).unwrap();
+    assert_eq!(target.into_inner(), VCD_TARGET_OUTPUT);
+    assert_eq!(resumed, &checkpoints[1..]);
+
+    // offsets inside the second window, past the end of the patch and past any seek
+    let resume = |checkpoint: &Checkpoint| {
+      let target = Cursor::new(VCD_TARGET_OUTPUT[..8].to_vec());
+      decode_resumable(None::<Cursor<Vec<u8>>>, Cursor::new(&VCD_TARGET_PATCH[..]), target, &options, Some(checkpoint), |_| {})
+    };
+    for &patch_offset in &[22, 10_000, u64::MAX] {
+      let result = resume(&Checkpoint { patch_offset, ..checkpoints[0] });
+      assert!(matches!(result, Err(XdeltaError::InvalidCheckpoint("checkpoint doesn't match the patch"))), "{:?}", result);
+    }
+    let result = resume(&Checkpoint { windows: 4, ..checkpoints[2] });
+    assert!(matches!(result, Err(XdeltaError::InvalidCheckpoint("patch has fewer windows than the checkpoint"))), "{:?}", result);
+    let result = resume(&Checkpoint { patch_length: 50, ..checkpoints[0] });
+    assert!(matches!(result, Err(XdeltaError::InvalidCheckpoint("checkpoint was saved for another patch"))), "{:?}", result);
+
+    // the target starts at position 1, so the end of the checkpoint doesn't fit in 64 bits
+    let huge = Checkpoint { target_offset: u64::MAX, ..checkpoints[0] };
+    let mut target = Cursor::new(vec![0]);
+    target.set_position(1);
+    let result = decode_resumable(None::<Cursor<Vec<u8>>>, Cursor::new(&VCD_TARGET_PATCH[..]), target, &options, Some(&huge), |_| {});
+    assert!(matches!(result, Err(XdeltaError::InvalidCheckpoint("checkpoint is past the largest position"))), "{:?}", result);
+  }
+
+  /// A secondary codec with state that carries over from one section to the next
+  struct Counting(u8);
+
+  impl SecondaryDecompressor for Counting {
+    fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), XdeltaError> {
+      for (out, byte) in output.iter_mut().zip(input) {
+        *out = byte ^ self.0;
+        self.0 = self.0.wrapping_add(1);
+      }
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn resume_rebuilds_secondary_state() {
+    let mut patch = vec![0xD6, 0xC3, 0xC4, 0x00, 0x01, 43];
+    let mut counter = 0u8;
+    for text in [&b"first"[..], b"second", b"third"].iter() {
+      let data : Vec<u8> = text.iter().map(|byte| { counter = counter.wrapping_add(1); byte ^ counter.wrapping_sub(1) }).collect();
+      patch.extend_from_slice(&[0x00, 0x07 + text.len() as u8, text.len() as u8, 0x01, 1 + text.len() as u8, 0x01, 0x00, text.len() as u8]);
+      patch.extend_from_slice(&data);
+      patch.push(text.len() as u8 + 1);
+    }
+    let mut options = DecodeOptions::default();
+    options.secondary.register(43, || Ok(Box::new(Counting(0))));
+    let (output, checkpoints) = checkpoints(&patch, &options);
+    assert_eq!(output, b"firstsecondthird");
+
+    let mut target = Cursor::new(b"firstsecond".to_vec());
+    decode_resumable(None::<Cursor<Vec<u8>>>, Cursor::new(&patch), &mut target, &options, Some(&checkpoints[1]), |_| {}).unwrap();
+    assert_eq!(target.into_inner(), b"firstsecondthird");
+  }
+
+  #[test]
+  fn resume_file() {
+    let dir = TempDir::new("resume");
+    let patch = dir.join("patch.vcdiff");
+    let target = dir.join("out");
+    fs::write(&patch, &VCD_TARGET_PATCH[..]).unwrap();
+    let mut saved = Vec::new();
+    decode_file_resumable(None, patch.clone(), target.clone(), None, |checkpoint| saved = checkpoint.to_bytes()).unwrap();
+    assert_eq!(fs::read(&target).unwrap(), VCD_TARGET_OUTPUT);
+
+    // resuming after the last window only truncates what the interrupted run left behind
+    let mut leftover = VCD_TARGET_OUTPUT.to_vec();
+    leftover.extend_from_slice(b"partial window of another run");
+    fs::write(&target, leftover).unwrap();
+    let checkpoint = Checkpoint::from_bytes(&saved).unwrap();
+    decode_file_resumable(None, patch, target.clone(), Some(&checkpoint), |_| {}).unwrap();
+    assert_eq!(fs::read(&target).unwrap(), VCD_TARGET_OUTPUT);
+  }
+
+  #[test]
+  fn progress_and_cancellation() {
+    let dir = TempDir::new("progress");
+    let patch = dir.join("patch.vcdiff");
+    let target = dir.join("out");
+    fs::write(&patch, &VCD_TARGET_PATCH[..]).unwrap();
+    let mut reports = Vec::new();
+    decode_file_with_progress(None, patch, target, |progress: &Progress| {
+      reports.push(*progress);
+      ControlFlow::Continue(())
+    }).unwrap();
+    let windows : Vec<u64> = reports.iter().map(|progress| progress.window).collect();
+    assert_eq!(windows, [0, 1, 2]);
+    assert_eq!(reports[0], Progress { window: 0, patch_bytes: 21, patch_total: Some(49), target_bytes: 8 });
+    assert_eq!((reports[2].patch_bytes, reports[2].target_bytes), (49, VCD_TARGET_OUTPUT.len() as u64));
+
+    let mut target = Cursor::new(Vec::new());
+    let options = DecodeOptions::default();
+    let result = decode_with_progress(None::<Cursor<Vec<u8>>>, &VCD_TARGET_PATCH[..], &mut target, &options, None, |_: &Progress| ControlFlow::Break(()));
+    assert!(matches!(result, Err(XdeltaError::Cancelled { windows: 1 })));
+    assert_eq!(target.into_inner(), &VCD_TARGET_OUTPUT[..8]);
+  }
+
+  fn patch_with_code_table(near_cache_size: u8, same_cache_size: u8, table: &[u8], window: &[u8]) -> Vec<u8> {
+    let compressed = encode(&CodeTable::default().to_bytes(), table);
+    let mut patch = vec![0xD6, 0xC3, 0xC4, 0x00, 0x02];
+    encode_base7_int(2 + compressed.len() as u64, &mut patch);
+    patch.extend_from_slice(&[near_cache_size, same_cache_size]);
+    patch.extend_from_slice(&compressed);
+    patch.extend_from_slice(window);
+    patch
+  }
+
+  #[test]
+  fn application_defined_code_table() {
+    // turn entry 255 into a single ADD of size 3
+    let mut table = CodeTable::default().to_bytes();
+    for (array, value) in [1, 0, 3, 0, 0, 0].iter().enumerate() {
+      table[array * 256 + 255] = *value;
+    }
+    let window = [0x00, 0x09, 0x03, 0x00, 0x03, 0x01, 0x00, b'a', b'b', b'c', 0xFF];
+    assert_eq!(decode_to_vec(&[], &patch_with_code_table(4, 3, &table, &window)).unwrap(), b"abc");
+
+    // the default table uses COPY modes that don't exist without caches
+    let default_table = CodeTable::default().to_bytes();
+    let result = decode_to_vec(&[], &patch_with_code_table(0, 0, &default_table, &window));
+    assert!(matches!(result, Err(XdeltaError::InvalidCodeTable(_))));
+  }
+
+  #[test]
+  fn nested_code_tables() {
+    // every code table delta announces a code table of its own
+    let mut patch = encode(&CodeTable::default().to_bytes(), &CodeTable::default().to_bytes());
+    for _ in 0..20_000 {
+      let mut outer = vec![0xD6, 0xC3, 0xC4, 0x00, 0x02];
+      encode_base7_int(2 + patch.len() as u64, &mut outer);
+      outer.extend_from_slice(&[4, 3]);
+      outer.extend_from_slice(&patch);
+      patch = outer;
+    }
+    let result = decode_to_vec(&[], &patch);
+    assert!(matches!(result, Err(XdeltaError::InvalidCodeTable(_))), "{:?}", result);
+
+    // the code table delta is decoded with the caller's limits
+    let patch = patch_with_code_table(4, 3, &CodeTable::default().to_bytes(), &ADD_ONLY_PATCH[5..]);
+    let options = DecodeOptions { limits: DecodeLimits { max_window_size: 1000, ..DecodeLimits::default() }, ..DecodeOptions::default() };
+    let result = decode_with_options(None::<Cursor<Vec<u8>>>, &patch[..], Cursor::new(Vec::new()), &options);
+    assert!(matches!(result, Err(XdeltaError::LimitExceeded { limit: "max_window_size", .. })), "{:?}", result);
+    assert_eq!(decode_to_vec(&[], &patch).unwrap(), b"abc");
+  }
+
+  /// SHA-256 plugged in as the target digest
+  struct Sha256(sha2::Sha256);
+
+  impl TargetHasher for Sha256 {
+    fn update(&mut self, bytes: &[u8]) {
+      sha2::Digest::update(&mut self.0, bytes);
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+      sha2::Digest::finalize_reset(&mut self.0).to_vec()
+    }
+  }
+
+  #[test]
+  fn target_digest() {
+    let options = DecodeOptions::default();
+    let summary = decode_with_summary(None::<Cursor<Vec<u8>>>, &VCD_TARGET_PATCH[..], Cursor::new(Vec::new()), &options, Adler32::new()).unwrap();
+    assert_eq!((summary.windows, summary.patch_bytes, summary.target_bytes), (3, 49, 48));
+    assert_eq!(summary.digest, ::adler32::adler32(VCD_TARGET_OUTPUT).to_be_bytes());
+
+    let summary = decode_with_summary(None::<Cursor<Vec<u8>>>, &VCD_TARGET_PATCH[..5], Cursor::new(Vec::new()), &options, Adler32::new()).unwrap();
+    assert_eq!((summary.windows, summary.patch_bytes, summary.target_bytes, summary.digest), (0, 5, 0, vec![0, 0, 0, 1]));
+
+    let dir = TempDir::new("summary");
+    let source : Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let target = source[500..10_500].to_vec();
+    fs::write(dir.join("source"), &source).unwrap();
+    fs::write(dir.join("patch.vcdiff"), encode(&source, &target)).unwrap();
+    let summary = decode_file_with_summary(Some(dir.join("source")), dir.join("patch.vcdiff"), dir.join("out"), Sha256(sha2::Digest::new())).unwrap();
+    assert_eq!(summary.digest, <sha2::Sha256 as sha2::Digest>::digest(&target).to_vec());
+    assert_eq!(summary.target_bytes, 10_000);
+  }
+
+  #[test]
+  fn bad_magic() {
+    let dir = TempDir::new("bad-magic");
+    let patch = dir.join("patch.vcdiff");
+    fs::write(&patch, b"PK\x03\x04\x00").unwrap();
+    let result = decode_file(None, patch.clone(), dir.join("out"));
+    assert!(matches!(result, Err(XdeltaError::InvalidMagic([b'P', b'K', 3, 4]))));
+  }
+
+  #[test]
+  fn format_detection() {
+    assert!(is_vcdiff(&ADD_ONLY_PATCH));
+    assert!(is_vcdiff(&ADD_ONLY_PATCH[..4]));
+    assert!(!is_vcdiff(&ADD_ONLY_PATCH[..3]));
+    assert!(!is_vcdiff(b"PK\x03\x04\x00"));
+    assert!(!is_vcdiff(&[0xD6, 0xC3, 0xC4, 0x01, 0x00]));
+    assert!(!is_vcdiff(&[0xD6, 0xC3, 0xC4, 0x00, 0x08]));
+
+    let result = decode_to_vec(&[], &[0xD6, 0xC3, 0xC4, 0x01, 0x00]);
+    assert!(matches!(result, Err(XdeltaError::UnsupportedVersion(1))));
+    let result = decode_to_vec(&[], &[0xD6, 0xC3, 0xC4, 0x00, 0x80]);
+    assert!(matches!(result, Err(XdeltaError::ReservedBits { indicator: "header indicator", value: 0x80 })));
+
+    let mut patch = ADD_ONLY_PATCH;
+    patch[5] = 0x03; // VCD_SOURCE and VCD_TARGET
+    assert!(matches!(decode_to_vec(&[], &patch), Err(XdeltaError::ReservedBits { indicator: "window indicator", .. })));
+    let mut patch = ADD_ONLY_PATCH;
+    patch[8] = 0x10;
+    assert!(matches!(decode_to_vec(&[], &patch), Err(XdeltaError::ReservedBits { indicator: "delta indicator", value: 0x10 })));
+  }
+
+  #[test]
+  fn decode_limits() {
+    let huge = |prefix: &[u8], suffix: &[u8]| {
+      let mut patch = prefix.to_vec();
+      encode_base7_int(1 << 40, &mut patch);
+      patch.extend_from_slice(suffix);
+      patch
+    };
+    let exceeds = |patch: &[u8], options: &DecodeOptions, name: &str| {
+      let result = decode_with_options(None::<Cursor<Vec<u8>>>, patch, Cursor::new(Vec::new()), options);
+      match result {
+        Err(XdeltaError::LimitExceeded { limit, .. }) => assert_eq!(limit, name),
+        other => panic!("expected {} to be exceeded, got {:?}", name, other),
+      }
+    };
+    let options = DecodeOptions::default();
+    let header = [0xD6, 0xC3, 0xC4, 0x00, 0x00];
+    exceeds(&huge(&[0xD6, 0xC3, 0xC4, 0x00, 0x04], &[]), &options, "max_app_header");
+    exceeds(&huge(&[0xD6, 0xC3, 0xC4, 0x00, 0x02], &[0, 0]), &options, "max_app_header");
+    exceeds(&huge(&[&header[..], &[0x00]].concat(), &[]), &options, "max_window_size");
+    exceeds(&huge(&[&header[..], &[0x00, 0x09]].concat(), &[0x00, 0x00, 0x00, 0x00]), &options, "max_window_size");
+
+    // a secondary section that decompresses to more than a window
+    let mut options = DecodeOptions::default();
+    options.secondary.register(42, || Ok(Box::new(Inverted)));
+    exceeds(&huge(&[0xD6, 0xC3, 0xC4, 0x00, 0x01, 42, 0x00, 0x0C, 0x03, 0x01, 0x06, 0x01, 0x00], &[0x04]), &options, "max_window_size");
+
+    let options = DecodeOptions { limits: DecodeLimits { max_target_size: 2, ..DecodeLimits::default() }, ..DecodeOptions::default() };
+    exceeds(&ADD_ONLY_PATCH, &options, "max_target_size");
+    let options = DecodeOptions { limits: DecodeLimits { max_target_size: 3, ..DecodeLimits::default() }, ..DecodeOptions::default() };
+    decode_with_options(None::<Cursor<Vec<u8>>>, &ADD_ONLY_PATCH[..], Cursor::new(Vec::new()), &options).unwrap();
+  }
+
+  #[test]
+  fn malformed_instructions() {
+    let invalid = |patch: &[u8], source: &[u8], details: &str| {
+      match decode_to_vec(source, patch) {
+        Err(XdeltaError::InvalidWindow(message)) => assert_eq!(message, details),
+        other => panic!("expected \"{}\", got {:?}", details, other),
+      }
+    };
+    let mut add_only = ADD_ONLY_PATCH;
+    add_only[7] = 0x02;
+    invalid(&add_only, &[], "instructions produce more than the target window length");
+    add_only[7] = 0x04;
+    invalid(&add_only, &[], "instructions produce less than the target window length");
+    // a delta encoding one byte longer than the window, which another byte after it would fill
+    add_only[7] = 0x03;
+    add_only[6] = 0x0A;
+    invalid(&[&add_only[..], &[0x00]].concat(), &[], "delta encoding length doesn't match the window");
+    add_only[6] = 0x08;
+    invalid(&add_only, &[], "delta encoding length doesn't match the window");
+
+    // COPY 5 in HERE mode from a source of 11 bytes, `here` is 11
+    let mut copy = [0xD6, 0xC3, 0xC4, 0x00, 0x00,
+                    0x01, 0x0B, 0x00, 0x07, 0x05, 0x00, 0x00, 0x01, 0x01, 0x25, 0x0C];
+    invalid(&copy, b"hello world", "HERE address points before the source segment");
+    copy[15] = 0x00;
+    invalid(&copy, b"hello world", "COPY address is not before the current position");
+    copy[15] = 0x0B;
+    assert_eq!(decode_to_vec(b"hello world", &copy).unwrap(), b"hello");
+    copy[15] = 0x03;
+    invalid(&copy, b"hello world", "COPY crosses the end of the segment");
+
+    // the first COPY of the second window reads target[1..9] from a segment of 8 bytes
+    let mut patch = VCD_TARGET_PATCH;
+    patch[35] = 0x01;
+    invalid(&patch, &[], "COPY crosses the end of the segment");
+
+    let mut patch = copy[..5].to_vec();
+    patch.extend_from_slice(&[0x01, 0x0B]);
+    encode_base7_int(u64::MAX, &mut patch);
+    patch.extend_from_slice(&copy[8..]);
+    invalid(&patch, b"hello world", "source segment ends past the largest position");
+  }
 }
@@ -1,6 +1,6 @@
 #[repr(C)]
 #[derive(Clone, Copy)]
-#[allow(dead_code)]
+#[allow(dead_code, clippy::enum_variant_names)]
 pub enum LzmaAction {
 	LzmaRun           = 0,
 	LzmaSyncFlush     = 1,
@@ -9,8 +9,8 @@ pub enum LzmaAction {
 	LzmaFinish        = 3,
 }
 
-impl Into<u32> for LzmaAction {
-  fn into(self) -> u32 {
-    self as u32
+impl From<LzmaAction> for u32 {
+  fn from(action: LzmaAction) -> u32 {
+    action as u32
   }
 }
\ No newline at end of file
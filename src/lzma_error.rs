@@ -1,7 +1,9 @@
 use std::convert::From;
+#[cfg(all(feature = "lzma-sys", not(feature = "pure-rust-lzma")))]
 use std::result::Result;
 use std::io::Error as IoError;
 
+#[cfg(all(feature = "lzma-sys", not(feature = "pure-rust-lzma")))]
 use super::lzma_ret;
 
 
@@ -43,6 +45,8 @@ impl std::fmt::Display for LzmaError {
 	}
   }
 
+impl std::error::Error for LzmaError {}
+
 impl From<IoError> for LzmaError {
 	fn from(err: IoError) -> LzmaError {
 		LzmaError::Io(err)
@@ -51,8 +55,10 @@ impl From<IoError> for LzmaError {
 
 
 /* Return values from liblzma are converted into this for easier handling */
+#[cfg(all(feature = "lzma-sys", not(feature = "pure-rust-lzma")))]
 pub struct LzmaLibResult;
 
+#[cfg(all(feature = "lzma-sys", not(feature = "pure-rust-lzma")))]
 impl LzmaLibResult {
 	pub fn from(ret: lzma_ret) -> Result<lzma_ret, LzmaError> {
 		match ret {
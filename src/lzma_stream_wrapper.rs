@@ -15,6 +15,7 @@ pub struct LzmaCodeResult {
 	/// The return value of lzma_code
 	pub ret: Result<lzma_ret, LzmaError>,
 	/// The number of bytes read from input
+	#[allow(dead_code)]
 	pub bytes_read: usize,
 	/// The number of bytes written to output
 	pub bytes_written: usize,
@@ -30,7 +31,7 @@ unsafe impl Send for LzmaStreamWrapper {}
 impl LzmaStreamWrapper {
 	pub fn new() -> LzmaStreamWrapper {
 		LzmaStreamWrapper {
-			stream: unsafe { lzma_stream::from(mem::zeroed()) },
+			stream: unsafe { mem::zeroed() },
 		}
 	}
 
@@ -55,7 +56,7 @@ impl LzmaStreamWrapper {
 				LzmaLibResult::from(lzma_code(&mut self.stream, action as lzma_sys::lzma_action))
 			};
 
-			if ret.is_err() || self.stream.avail_in <= 0 {
+			if ret.is_err() || self.stream.avail_in == 0 {
 				break;
 			}
 		}
@@ -69,9 +70,9 @@ impl LzmaStreamWrapper {
 		self.stream.avail_out = 0;
 
 		LzmaCodeResult {
-			ret: ret,
-			bytes_read: bytes_read,
-			bytes_written: bytes_written,
+			ret,
+			bytes_read,
+			bytes_written,
 		}
 	}
 }
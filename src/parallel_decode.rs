@@ -0,0 +1,186 @@
+//! `decode_parallel`, available with the `parallel` feature.
+//!
+//! Windows are read in batches. The secondary decompression of their sections runs in patch
+//! order, since decompressors keep state across windows, but a VCD_SOURCE window only depends
+//! on the source and its own sections after that. The windows of a batch are decoded on rayon's
+//! thread pool and written in order, each at the target offset that follows the window before.
+//! A VCD_TARGET window copies from earlier output, so the batch before it is written first and
+//! it is decoded on its own.
+
+use patch_reader::PatchReader;
+use vcdiff_code_table::CodeTable;
+use vcdiff_source::{Source, SeekSource};
+#[cfg(feature = "mmap")]
+use vcdiff_source::map_file;
+use vcdiff_target::{Target, SeekTarget};
+use vcdiff_window::Window;
+use decode_options::{check_limit, DecodeOptions};
+use xdelta_error::XdeltaError;
+use truncate_corrupt_window;
+
+use rayon::prelude::*;
+
+use std::fs::{File, OpenOptions};
+use std::io::{Cursor, Read, Write, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Upper bound on the target bytes of the windows decoded at the same time
+const BATCH_BYTES: u64 = 256 << 20;
+
+/**
+* Same as `decode_file`, decoding the VCD_SOURCE windows of the patch on several threads.
+* The threads share the source file when it is mapped into memory (`mmap` feature), otherwise
+* they open it for themselves.
+*/
+pub fn decode_file_parallel<P: AsRef<Path>>(source_file_path: Option<P>, patch_file_path: P, target_file_path: P) -> Result<(), XdeltaError> {
+  // fail early if the source can't be opened
+  let source = match source_file_path {
+    Some(path) => Some((path.as_ref().to_path_buf(), File::open(path.as_ref())?)),
+    None => None,
+  };
+  let patch = OpenOptions::new().read(true).open(patch_file_path)?;
+  let mut target = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(target_file_path)?;
+  #[cfg(feature = "mmap")]
+  {
+    if let Some(map) = source.as_ref().and_then(|(_, file)| map_file(file)) {
+      let result = apply_parallel(Some(&map[..]), patch, SeekTarget::new(&mut target)?, &DecodeOptions::default());
+      return truncate_corrupt_window(result, &mut target);
+    }
+  }
+  let source = source.map(|(path, file)| PathSource { path: Arc::new(path), file: Some(SeekSource(file)) });
+  let result = apply_parallel(source, patch, SeekTarget::new(&mut target)?, &DecodeOptions::default());
+  truncate_corrupt_window(result, &mut target)
+}
+
+/**
+* Same as `decode_with_options` for an in-memory source, decoding the VCD_SOURCE windows of the
+* patch on several threads.
+*/
+pub fn decode_parallel<P: Read, T: Read + Write + Seek>(source: &[u8], patch: P, target: T, options: &DecodeOptions) -> Result<(), XdeltaError> {
+  apply_parallel(Some(source), patch, SeekTarget::new(target)?, options)
+}
+
+/// A source file that is opened again by every thread that reads from it
+struct PathSource {
+  path: Arc<PathBuf>,
+  file: Option<SeekSource<File>>,
+}
+
+impl Clone for PathSource {
+  fn clone(&self) -> PathSource {
+    PathSource { path: self.path.clone(), file: None }
+  }
+}
+
+impl Source for PathSource {
+  fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> Result<(), XdeltaError> {
+    if self.file.is_none() {
+      self.file = Some(SeekSource(File::open(self.path.as_ref())?));
+    }
+    self.file.as_mut().unwrap().read_at(pos, buf)
+  }
+}
+
+/// Decodes a VCD_SOURCE or target-only window into memory
+fn decode_alone<S: Source>((index, window): (u64, Window), source: &mut Option<S>, code_table: &CodeTable, options: &DecodeOptions) -> Result<Vec<u8>, XdeltaError> {
+  let mut output = Cursor::new(Vec::with_capacity(window.target_window_length as usize));
+  window.decode_window(index, source, &mut SeekTarget::new(&mut output)?, code_table, options)?;
+  Ok(output.into_inner())
+}
+
+fn apply_parallel<S, P, T>(mut source: Option<S>, patch: P, mut target: T, options: &DecodeOptions) -> Result<(), XdeltaError>
+  where S: Source + Clone + Send + Sync, P: Read, T: Target {
+  let windows = PatchReader::new(patch, &options.secondary, &options.limits)?;
+  let code_table = windows.code_table.clone();
+  let batch_windows = 2 * rayon::current_num_threads();
+
+  let mut windows = windows.enumerate().map(|(index, window)| window.map(|window| (index as u64, window)));
+  let mut batch = Vec::new();
+  let mut batch_bytes = 0;
+  loop {
+    let next = windows.next().transpose()?;
+    let target_relative = match next {
+      Some((_, ref window)) => window.window_indicator & 2 != 0,
+      None => false,
+    };
+    let full = batch.len() >= batch_windows || batch_bytes >= BATCH_BYTES;
+    if next.is_none() || target_relative || full {
+      let outputs : Vec<Result<Vec<u8>, XdeltaError>> = std::mem::take(&mut batch)
+        .into_par_iter()
+        .map_init(|| source.clone(), |source, window| decode_alone(window, source, &code_table, options))
+        .collect();
+      for output in outputs {
+        target.write_all(&output?)?;
+        target.end_window();
+      }
+      batch_bytes = 0;
+    }
+    match next {
+      Some((index, window)) if target_relative => window.decode_window(index, &mut source, &mut target, &code_table, options)?,
+      Some((index, window)) => {
+        // the windows of the batch are decoded into targets of their own, so the limit is checked here
+        let target_end = target.position().saturating_add(batch_bytes).saturating_add(window.target_window_length);
+        check_limit("max_target_size", options.limits.max_target_size, target_end)?;
+        batch_bytes += window.target_window_length;
+        batch.push((index, window));
+      },
+      None => return Ok(()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{decode_file_parallel, decode_parallel};
+  use tests::{TempDir, VCD_TARGET_PATCH};
+  use {decode_to_vec, encode, DecodeOptions, XdeltaError};
+  use std::fs;
+  use std::io::Cursor;
+
+  /// Bytes without repetitions, so that each target can only be copied from one place
+  fn source() -> Vec<u8> {
+    (0..20000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect()
+  }
+
+  /// Target-only and VCD_TARGET windows, then one VCD_SOURCE window per target, then the
+  /// VCD_TARGET windows again
+  fn mixed_patch(source: &[u8]) -> Vec<u8> {
+    let mut patch = VCD_TARGET_PATCH.to_vec();
+    for i in 0..40 {
+      let mut target = source[i * 300..i * 300 + 2000].to_vec();
+      target.extend_from_slice(format!("window {}", i).as_bytes());
+      patch.extend_from_slice(&encode(source, &target)[5..]);
+    }
+    patch.extend_from_slice(&VCD_TARGET_PATCH[21..]);
+    patch
+  }
+
+  #[test]
+  fn matches_sequential_decoding() {
+    let source = source();
+    let patch = mixed_patch(&source);
+    let expected = decode_to_vec(&source, &patch).unwrap();
+
+    let mut output = Cursor::new(Vec::new());
+    decode_parallel(&source, &patch[..], &mut output, &DecodeOptions::default()).unwrap();
+    assert!(output.into_inner() == expected);
+
+    let dir = TempDir::new("parallel");
+    fs::write(dir.join("source"), &source).unwrap();
+    fs::write(dir.join("patch.vcdiff"), &patch).unwrap();
+    decode_file_parallel(Some(dir.join("source")), dir.join("patch.vcdiff"), dir.join("out")).unwrap();
+    assert!(fs::read(dir.join("out")).unwrap() == expected);
+  }
+
+  #[test]
+  fn reports_the_failing_window() {
+    let source = source();
+    let patch = mixed_patch(&source);
+    // only the last VCD_SOURCE window copies from the end of the source
+    let mut changed = source.clone();
+    changed[13650] ^= 1;
+    let result = decode_parallel(&changed, &patch[..], Cursor::new(Vec::new()), &DecodeOptions::default());
+    assert!(matches!(result, Err(XdeltaError::ChecksumMismatch { window: 42, .. })), "{:?}", result);
+  }
+}
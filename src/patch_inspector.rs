@@ -0,0 +1,209 @@
+use patch_reader::PatchReader;
+use secondary_decompressor::SecondaryRegistry;
+use vcdiff_code_table::CodeTable;
+use vcdiff_header::Header;
+use vcdiff_instructions::Instructions;
+use vcdiff_source::SeekSource;
+use vcdiff_target::{HistoryTarget, SeekTarget};
+use vcdiff_window::Window;
+use decode_options::{DecodeLimits, DecodeOptions};
+use xdelta_error::XdeltaError;
+use apply_windows;
+
+use std::io::{Read, Seek, Write};
+use std::ops::ControlFlow;
+use std::sync::Arc;
+
+/// The header fields of one window as stored in the patch, what `xdelta3 printhdrs` shows
+#[derive(Debug)]
+pub struct WindowInfo {
+  /// Position of the window in the patch, starting at 0
+  pub index: u64,
+  /// VCD_SOURCE (1), VCD_TARGET (2) and the xdelta3 extension VCD_ADLER32 (4)
+  pub window_indicator: u8,
+  /// Length and position of the segment that COPY instructions read from
+  pub source_segment: Option<(u64, u64)>,
+  pub delta_encoding_length: u64,
+  pub target_window_length: u64,
+  /// Which sections are secondary compressed: data (1), instructions (2), addresses (4)
+  pub delta_indicator: u8,
+  /// Lengths of the sections as stored in the patch, i.e. before secondary decompression
+  pub data_length: u64,
+  pub instructions_length: u64,
+  pub addresses_length: u64,
+  pub adler32_checksum: Option<u32>,
+  window: Window,
+  code_table: Arc<CodeTable>,
+}
+
+impl WindowInfo {
+  /// COPY instructions read the source segment from the source file
+  pub fn is_source(&self) -> bool {
+    self.window_indicator & 1 != 0
+  }
+
+  /// COPY instructions read the source segment from earlier output
+  pub fn is_target(&self) -> bool {
+    self.window_indicator & 2 != 0
+  }
+
+  /// The decoded instructions of the window, what `xdelta3 printdelta` shows
+  pub fn instructions(&self) -> Instructions<'_> {
+    self.window.instructions(&self.code_table)
+  }
+}
+
+/**
+* Reads a patch without applying it: `header()` gives the file header, iterating yields the
+* windows one by one. Secondary compressed sections are decompressed so their instructions
+* can be listed.
+*/
+pub struct PatchInspector<P> {
+  reader: PatchReader<P>,
+  index: u64,
+}
+
+impl<P: Read> PatchInspector<P> {
+  pub fn new(patch: P) -> Result<PatchInspector<P>, XdeltaError> {
+    PatchInspector::with_registry(patch, &SecondaryRegistry::default())
+  }
+
+  /// Same as `new`, with the secondary decompressors from `registry`
+  pub fn with_registry(patch: P, registry: &SecondaryRegistry) -> Result<PatchInspector<P>, XdeltaError> {
+    Ok(PatchInspector {
+      reader: PatchReader::new(patch, registry, &DecodeLimits::default())?,
+      index: 0,
+    })
+  }
+
+  /// Same as `new`, with the secondary decompressors and limits of `options`
+  pub fn with_options(patch: P, options: &DecodeOptions) -> Result<PatchInspector<P>, XdeltaError> {
+    Ok(PatchInspector {
+      reader: PatchReader::new(patch, &options.secondary, &options.limits)?,
+      index: 0,
+    })
+  }
+
+  pub fn header(&self) -> &Header {
+    &self.reader.header
+  }
+
+  /**
+  * Applies the patch like `decode_with_options`. The header doesn't have to be read a second time,
+  * so the target can be chosen after looking at it even when the patch comes from a pipe. Fails
+  * with `WindowsAlreadyRead` once windows were iterated, their output would be missing.
+  */
+  pub fn decode<S: Read + Seek, T: Read + Write + Seek>(self, source: Option<S>, target: T, options: &DecodeOptions) -> Result<(), XdeltaError> {
+    let reader = self.unread()?;
+    apply_windows(reader, 0, source.map(SeekSource), SeekTarget::new(target)?, options, None, |_| ControlFlow::Continue(()))
+  }
+
+  /// Same as `decode`, like `decode_to_writer` for targets that can't be read back
+  pub fn decode_to_writer<S: Read + Seek, W: Write>(self, source: Option<S>, target: W, options: &DecodeOptions) -> Result<(), XdeltaError> {
+    let reader = self.unread()?;
+    apply_windows(reader, 0, source.map(SeekSource), HistoryTarget::new(target, options.target_history), options, None, |_| ControlFlow::Continue(()))
+  }
+
+  /// The patch reader, if no windows were taken from it yet
+  fn unread(self) -> Result<PatchReader<P>, XdeltaError> {
+    if self.index > 0 {
+      return Err(XdeltaError::WindowsAlreadyRead { windows: self.index });
+    }
+    Ok(self.reader)
+  }
+}
+
+impl<P: Read> Iterator for PatchInspector<P> {
+  type Item = Result<WindowInfo, XdeltaError>;
+
+  fn next(&mut self) -> Option<Result<WindowInfo, XdeltaError>> {
+    let window = match self.reader.next()? {
+      Ok(window) => window,
+      Err(err) => return Some(Err(err)),
+    };
+    let index = self.index;
+    self.index += 1;
+    Some(Ok(WindowInfo {
+      index,
+      window_indicator: window.window_indicator,
+      source_segment: window.source_segment,
+      delta_encoding_length: window.delta_encoding_length,
+      target_window_length: window.target_window_length,
+      delta_indicator: window.delta_indicator,
+      data_length: window.data_length,
+      instructions_length: window.instructions_length,
+      addresses_length: window.addresses_length,
+      adler32_checksum: window.adler32_checksum.map(u32::from_be_bytes),
+      window,
+      code_table: self.reader.code_table.clone(),
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::PatchInspector;
+  use vcdiff_instructions::Operation;
+  use tests::{ADD_ONLY_PATCH, VCD_TARGET_PATCH, VCD_TARGET_OUTPUT};
+  use {encode, DecodeOptions, XdeltaError};
+  use std::io::Cursor;
+
+  #[test]
+  fn windows_and_instructions() {
+    let copy_window = [0x01, 0x0B, 0x00, 0x07, 0x05, 0x00, 0x00, 0x01, 0x01, 0x15, 0x00];
+    let patch = [&ADD_ONLY_PATCH[..5], &copy_window[..], &ADD_ONLY_PATCH[5..]].concat();
+    let mut inspector = PatchInspector::new(&patch[..]).unwrap();
+    assert_eq!(inspector.header().header, [0xD6, 0xC3, 0xC4, 0x00]);
+    assert_eq!(inspector.header().secondary_compressor_id, None);
+
+    let first = inspector.next().unwrap().unwrap();
+    assert!(first.is_source() && !first.is_target());
+    assert_eq!((first.source_segment, first.target_window_length, first.adler32_checksum), (Some((11, 0)), 5, None));
+    let operations : Vec<Operation> = first.instructions().collect::<Result<_, _>>().unwrap();
+    assert_eq!(operations, vec![Operation::Copy { address: 0, size: 5, mode: 0 }]);
+
+    let second = inspector.next().unwrap().unwrap();
+    assert_eq!((second.index, second.source_segment, second.data_length), (1, None, 3));
+    let operations : Vec<Operation> = second.instructions().collect::<Result<_, _>>().unwrap();
+    assert_eq!(operations, vec![Operation::Add { data: b"abc" }]);
+    assert!(inspector.next().is_none());
+  }
+
+  #[test]
+  fn sizes_add_up_to_the_window() {
+    let source = b"a source that the target copies from, a source".to_vec();
+    let target = b"the target copies from a source that the target copies from, twice".to_vec();
+    let patch = encode(&source, &target);
+    let windows : Vec<_> = PatchInspector::new(&patch[..]).unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(windows.len(), 1);
+    let total : u64 = windows[0].instructions().map(|operation| operation.unwrap().size()).sum();
+    assert_eq!(total, windows[0].target_window_length);
+    assert!(windows[0].adler32_checksum.is_some());
+  }
+
+  #[test]
+  fn decode_after_the_header() {
+    let options = DecodeOptions::default();
+    let inspector = PatchInspector::with_options(&VCD_TARGET_PATCH[..], &options).unwrap();
+    assert!(inspector.header().app_header().is_none());
+    let mut output = Vec::new();
+    inspector.decode_to_writer(None::<Cursor<Vec<u8>>>, &mut output, &options).unwrap();
+    assert_eq!(output, VCD_TARGET_OUTPUT);
+
+    let inspector = PatchInspector::with_options(&VCD_TARGET_PATCH[..], &options).unwrap();
+    let mut output = Cursor::new(Vec::new());
+    inspector.decode(None::<Cursor<Vec<u8>>>, &mut output, &options).unwrap();
+    assert_eq!(output.into_inner(), VCD_TARGET_OUTPUT);
+
+    // the first window was only listed, its output would be missing
+    let mut inspector = PatchInspector::with_options(&VCD_TARGET_PATCH[..], &options).unwrap();
+    inspector.next().unwrap().unwrap();
+    let result = inspector.decode_to_writer(None::<Cursor<Vec<u8>>>, Vec::new(), &options);
+    assert!(matches!(result, Err(XdeltaError::WindowsAlreadyRead { windows: 1 })), "{:?}", result);
+    let mut inspector = PatchInspector::with_options(&VCD_TARGET_PATCH[..], &options).unwrap();
+    inspector.next().unwrap().unwrap();
+    inspector.next().unwrap().unwrap();
+    let result = inspector.decode(None::<Cursor<Vec<u8>>>, Cursor::new(Vec::new()), &options);
+    assert!(matches!(result, Err(XdeltaError::WindowsAlreadyRead { windows: 2 })), "{:?}", result);
+  }
+}
@@ -0,0 +1,212 @@
+use vcdiff_header::Header;
+use checkpoint::Checkpoint;
+use vcdiff_window::Window;
+use vcdiff_code_table::CodeTable;
+use secondary_decompressor::{SecondaryDecompressor, SecondaryRegistry};
+use vcdiff_target::SeekTarget;
+use decode_options::{check_limit, DecodeLimits, DecodeOptions};
+use reader::Reader;
+use xdelta_error::XdeltaError;
+use decode_base7_int;
+
+use std::io::{Cursor, Read, Seek};
+use std::sync::Arc;
+
+/**
+* Reads the header of a patch and then yields its windows, with the secondary compression of
+* their sections already undone. The window metadata stays as it is in the patch.
+*/
+pub struct PatchReader<P> {
+  bytes: Reader<P>,
+  pub header: Header,
+  pub code_table: Arc<CodeTable>,
+  sections: SectionDecompressors,
+  failed: bool,
+}
+
+impl<P: Read> PatchReader<P> {
+  pub fn new(patch: P, secondary: &SecondaryRegistry, limits: &DecodeLimits) -> Result<PatchReader<P>, XdeltaError> {
+    let mut bytes = Reader::with_capacity(200, patch);
+
+    //read header
+    let header = Header::new(&mut bytes, limits)?;
+    Ok(PatchReader {
+      bytes,
+      code_table: Arc::new(code_table(&header, limits)?),
+      sections: SectionDecompressors::new(&header, secondary, limits)?,
+      header,
+      failed: false,
+    })
+  }
+
+  /// Position of the next window in the patch
+  pub fn offset(&self) -> u64 {
+    self.bytes.offset()
+  }
+
+  fn read_window(&mut self) -> Result<Window, XdeltaError> {
+    let mut window = Window::new(&mut self.bytes, &self.sections.limits)?;
+    self.sections.decompress(&mut window)?;
+    Ok(window)
+  }
+}
+
+impl<P: Read + Seek> PatchReader<P> {
+  /// Length of the whole patch, measured by seeking to its end
+  pub fn length(&mut self) -> Result<u64, XdeltaError> {
+    Ok(self.offset() + self.bytes.remaining()?)
+  }
+
+  /// Moves on to the first window after `checkpoint`. Sections are read again if secondary
+  /// decompressors have to catch up, otherwise the applied windows are skipped by their lengths.
+  pub fn resume(&mut self, checkpoint: &Checkpoint) -> Result<(), XdeltaError> {
+    let length = self.length()?;
+    if checkpoint.patch_length != length {
+      return Err(XdeltaError::InvalidCheckpoint("checkpoint was saved for another patch"));
+    }
+    for _ in 0..checkpoint.windows {
+      if self.bytes.peek()?.is_none() {
+        return Err(XdeltaError::InvalidCheckpoint("patch has fewer windows than the checkpoint"));
+      }
+      if self.header.secondary_compressor_id.is_some() {
+        self.read_window()?;
+      } else {
+        self.skip_window(length)?;
+      }
+    }
+    if self.offset() != checkpoint.patch_offset {
+      return Err(XdeltaError::InvalidCheckpoint("checkpoint doesn't match the patch"));
+    }
+    Ok(())
+  }
+
+  /// Skips a window without reading its sections, `length` is the length of the patch
+  fn skip_window(&mut self, length: u64) -> Result<(), XdeltaError> {
+    let window_indicator = self.bytes.read_byte()?;
+    if window_indicator % 4 != 0 { //VCD_SOURCE || VCD_TARGET
+      self.bytes.decode_base7_int()?;
+      self.bytes.decode_base7_int()?;
+    }
+    let delta_encoding_length = self.bytes.decode_base7_int()?;
+    if delta_encoding_length > length - self.offset() {
+      return Err(XdeltaError::UnexpectedEof);
+    }
+    self.bytes.skip(delta_encoding_length)
+  }
+}
+
+impl<P: Read> Iterator for PatchReader<P> {
+  type Item = Result<Window, XdeltaError>;
+
+  fn next(&mut self) -> Option<Result<Window, XdeltaError>> {
+    if self.failed {
+      return None;
+    }
+    let window = match self.bytes.peek() {
+      Ok(None) => return None,
+      Ok(Some(_)) => self.read_window(),
+      Err(e) => Err(e.into()),
+    };
+    self.failed = window.is_err();
+    Some(window)
+  }
+}
+
+/// The code table windows of the patch are decoded with
+pub fn code_table(header: &Header, limits: &DecodeLimits) -> Result<CodeTable, XdeltaError> {
+  //the application-defined code table is a delta against the default one
+  match header.code_table {
+    Some(ref custom) => {
+      let table = decode_code_table(&custom.compressed_data, limits)?;
+      CodeTable::from_bytes(custom.near_cache_size, custom.same_cache_size, &table)
+    },
+    None => Ok(CodeTable::default()),
+  }
+}
+
+/**
+* Applies the delta of an application-defined code table to the default table. The delta has to
+* use the default code table itself and no secondary compression, so this never recurses into
+* another code table delta.
+*/
+pub fn decode_code_table(delta: &[u8], limits: &DecodeLimits) -> Result<Vec<u8>, XdeltaError> {
+  let mut bytes = Reader::with_capacity(200, delta);
+  let header = Header::new(&mut bytes, limits)?;
+  if header.code_table.is_some() || header.secondary_compressor_id.is_some() {
+    return Err(XdeltaError::InvalidCodeTable("code table delta has a code table or secondary compression of its own"));
+  }
+  let default_table = CodeTable::default();
+  let source = default_table.to_bytes();
+  let options = DecodeOptions { limits: *limits, ..DecodeOptions::default() };
+  let mut table = Cursor::new(Vec::new());
+  {
+    let mut target = SeekTarget::new(&mut table)?;
+    let mut index = 0;
+    while bytes.peek()?.is_some() {
+      let window = Window::new(&mut bytes, limits)?;
+      window.decode_window(index, &mut Some(&source[..]), &mut target, &default_table, &options)?;
+      index += 1;
+    }
+  }
+  Ok(table.into_inner())
+}
+
+/// Undoes the secondary compression of the windows of one patch
+pub struct SectionDecompressors {
+  compressor_id: Option<u8>,
+  secondary: SecondaryRegistry,
+  limits: DecodeLimits,
+  /// one secondary decompressor per section kind (data, instructions, addresses), created on first use
+  decompressors: [Option<Box<dyn SecondaryDecompressor>>; 3],
+}
+
+impl SectionDecompressors {
+  pub fn new(header: &Header, secondary: &SecondaryRegistry, limits: &DecodeLimits) -> Result<SectionDecompressors, XdeltaError> {
+    if let Some(id) = header.secondary_compressor_id {
+      if !secondary.contains(id) {
+        return Err(XdeltaError::UnsupportedSecondaryCompressor(id));
+      }
+    }
+    Ok(SectionDecompressors {
+      compressor_id: header.secondary_compressor_id,
+      secondary: secondary.clone(),
+      limits: *limits,
+      decompressors: [None, None, None],
+    })
+  }
+
+  /// Replaces the compressed sections of `window` with their decompressed contents
+  pub fn decompress(&mut self, window: &mut Window) -> Result<(), XdeltaError> {
+    if window.delta_indicator == 0 {
+      return Ok(());
+    }
+    let id = match self.compressor_id {
+      Some(id) => id,
+      None => return Err(XdeltaError::InvalidWindow("compressed sections without a secondary compressor")),
+    };
+    let mut sections = [&mut window.data, &mut window.instructions, &mut window.addresses];
+    for (kind, section) in sections.iter_mut().enumerate() {
+      if window.delta_indicator & (1 << kind) == 0 {
+        continue;
+      }
+      if self.decompressors[kind].is_none() {
+        self.decompressors[kind] = Some(self.secondary.create(id)?);
+      }
+      let decompressor = self.decompressors[kind].as_mut().unwrap();
+      **section = decompress_section(section, decompressor.as_mut(), &self.limits)?;
+    }
+    Ok(())
+  }
+}
+
+/**
+* Undoes the secondary compression of one section: the decompressed size as an integer,
+* followed by the compressed bytes.
+*/
+fn decompress_section(section: &[u8], decompressor: &mut dyn SecondaryDecompressor, limits: &DecodeLimits) -> Result<Vec<u8>, XdeltaError> {
+  let size = decode_base7_int(&mut section.iter())?;
+  check_limit("max_window_size", limits.max_window_size, size.result)?;
+  let mut decoded : Vec<u8> = vec![0; size.result as usize];
+  decompressor.decompress(&section[size.bytes_read..], &mut decoded)?;
+  Ok(decoded)
+}
@@ -0,0 +1,29 @@
+use std::ops::ControlFlow;
+
+/// Where a decoding stands after a window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+  /// Index of the window that was just applied, starting at 0
+  pub window: u64,
+  /// Bytes of the patch read so far
+  pub patch_bytes: u64,
+  /// Size of the whole patch, if it's known, e.g. the length of the patch file
+  pub patch_total: Option<u64>,
+  /// Bytes of the target written so far
+  pub target_bytes: u64,
+}
+
+/**
+* Gets called after every window of the patch. Returning `ControlFlow::Break` stops the decoding
+* with `XdeltaError::Cancelled`, the target then holds the output of the windows before.
+* Implemented for closures taking a `&Progress`.
+*/
+pub trait ProgressObserver {
+  fn window_done(&mut self, progress: &Progress) -> ControlFlow<()>;
+}
+
+impl<F: FnMut(&Progress) -> ControlFlow<()>> ProgressObserver for F {
+  fn window_done(&mut self, progress: &Progress) -> ControlFlow<()> {
+    self(progress)
+  }
+}
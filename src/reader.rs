@@ -1,96 +1,113 @@
 use std::io::prelude::*;
 
 use std::cmp;
+use std::convert::TryFrom;
 use std::fmt;
-use std::io::{self, SeekFrom}; 
+use std::io::{self, SeekFrom};
 
-pub struct Reader {
-    inner: std::fs::File,
+use xdelta_error::XdeltaError;
+
+pub struct Reader<R> {
+    inner: R,
     buf: Box<[u8]>,
     pos: usize,
     cap: usize,
+    /// bytes handed out so far, the position in the patch
+    offset: u64,
 }
 
-impl Reader {
-    pub fn with_capacity(cap: usize, inner: std::fs::File) -> Reader {
-        unsafe {
-            let mut buffer = Vec::with_capacity(cap);
-            buffer.set_len(cap);
-            //inner.read(&mut buffer);
-            Reader {
-                inner,
-                buf: buffer.into_boxed_slice(),
-                pos: 0,
-                cap: 0,
-            }
+impl<R: Read> Reader<R> {
+    pub fn with_capacity(cap: usize, inner: R) -> Reader<R> {
+        Reader {
+            inner,
+            buf: vec![0; cap].into_boxed_slice(),
+            pos: 0,
+            cap: 0,
+            offset: 0,
         }
     }
 
-    pub fn peek(&mut self) -> Option<u8>{
-        if self.pos == self.cap && 1 >= self.buf.len() {
-            return None;
-        }
-        let mut next = None;
-        {
-            match self.fill_buf() {
-                Ok(array) => {
-                    if array.len() != 0 {
-                      next = Some(array[0]);
-                    }
-                },
-                Err(_e) => {}
-            }
-        }
-        //println!("{:?}", next);
-        return next;
+    /// Number of bytes read or skipped through this reader, seeking doesn't change it
+    pub fn offset(&self) -> u64 {
+        self.offset
     }
-    pub fn next(&mut self) -> Option<u8>{
+
+    /// The underlying reader, bytes in the internal buffer are already taken from it
+    #[cfg(feature = "async")]
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// The next byte without consuming it, `None` at the end of the patch
+    pub fn peek(&mut self) -> io::Result<Option<u8>> {
         if self.pos == self.cap && 1 >= self.buf.len() {
-            return None;
+            return Ok(None);
         }
-        let mut next = None;
-        {
+        loop {
             match self.fill_buf() {
-                Ok(array) => {
-                    next = Some(array[0]);
-                },
-                Err(_e) => {}
+                Ok(array) => return Ok(array.first().cloned()),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
             }
         }
-        if next.is_some() {
-            self.consume(1);
+    }
+
+    /// Reads a single byte, failing with `UnexpectedEof` at the end of the patch
+    pub fn read_byte(&mut self) -> Result<u8, XdeltaError> {
+        let byte = match self.fill_buf()?.first() {
+            Some(&byte) => byte,
+            None => return Err(XdeltaError::UnexpectedEof),
+        };
+        self.consume(1);
+        Ok(byte)
+    }
+
+    /// Reads the next `len` bytes of the patch, e.g. one of the sections of a window
+    /// The vector grows with what is read, so a truncated patch can't allocate the whole length
+    pub fn read_vec(&mut self, len: u64) -> Result<Vec<u8>, XdeltaError> {
+        let mut vec = Vec::new();
+        self.by_ref().take(len).read_to_end(&mut vec)?;
+        if (vec.len() as u64) < len {
+            return Err(XdeltaError::UnexpectedEof);
         }
-        //println!("{:?}", next);
-        return next;
+        Ok(vec)
     }
 
-    pub fn decode_base7_int(&mut self) -> DecodeResult {
+    pub fn decode_base7_int(&mut self) -> Result<u64, XdeltaError> {
       let mut result : u64 = 0;
       let mut not_finished : bool = true;
       let mut counter = 0;
       while not_finished {
         if counter == 10 {
-          return DecodeResult { result: None, bytes_read: counter };
+          return Err(XdeltaError::TruncatedVarint);
         }
         counter += 1;
-        let next_byte = self.next().unwrap();
+        let next_byte = match self.read_byte() {
+          Ok(byte) => byte,
+          Err(XdeltaError::UnexpectedEof) => return Err(XdeltaError::TruncatedVarint),
+          Err(e) => return Err(e),
+        };
+        if result >> 57 != 0 {
+          return Err(XdeltaError::TruncatedVarint); // doesn't fit in 64 bits
+        }
         result = (result << 7) | (next_byte as u64 & 127);
         if (next_byte & 128) == 0 {
           not_finished = false;
         }
       }
-      return DecodeResult { result: Some(result), bytes_read: counter };
+      Ok(result)
     }
 }
 
-
-impl Read for Reader {
+impl<R: Read> Read for Reader<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         // If we don't have any buffered data and we're doing a massive read
         // (larger than our internal buffer), bypass our internal buffer
         // entirely.
         if self.pos == self.cap && buf.len() >= self.buf.len() {
-            return self.inner.read(buf);
+            let nread = self.inner.read(buf)?;
+            self.offset += nread as u64;
+            return Ok(nread);
         }
         let nread = {
             let mut rem = self.fill_buf()?;
@@ -101,7 +118,7 @@ impl Read for Reader {
     }
 }
 
-impl BufRead for Reader {
+impl<R: Read> BufRead for Reader<R> {
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
         // If we've reached the end of our internal buffer then we need to fetch
         // some more data from the underlying reader.
@@ -116,11 +133,40 @@ impl BufRead for Reader {
     }
 
     fn consume(&mut self, amt: usize) {
-        self.pos = cmp::min(self.pos + amt, self.cap);
+        let pos = cmp::min(self.pos + amt, self.cap);
+        self.offset += (pos - self.pos) as u64;
+        self.pos = pos;
     }
 }
 
-impl fmt::Debug for Reader {
+impl<R: Read + Seek> Reader<R> {
+    /// Skips the next `amt` bytes, seeking past what isn't buffered
+    pub fn skip(&mut self, amt: u64) -> Result<(), XdeltaError> {
+        let buffered = (self.cap - self.pos) as u64;
+        if amt <= buffered {
+            self.consume(amt as usize);
+        } else {
+            let distance = match i64::try_from(amt - buffered) {
+                Ok(distance) => distance,
+                Err(_) => return Err(XdeltaError::InvalidCheckpoint("checkpoint is past the end of the patch")),
+            };
+            self.inner.seek(SeekFrom::Current(distance))?;
+            self.pos = self.cap;
+            self.offset += amt;
+        }
+        Ok(())
+    }
+
+    /// Number of bytes after the offset, found by seeking to the end of the patch
+    pub fn remaining(&mut self) -> io::Result<u64> {
+        let position = self.inner.stream_position()?;
+        let end = self.inner.seek(SeekFrom::End(0))?;
+        self.inner.seek(SeekFrom::Start(position))?;
+        Ok(end.saturating_sub(position) + (self.cap - self.pos) as u64)
+    }
+}
+
+impl<R: fmt::Debug> fmt::Debug for Reader<R> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.debug_struct("Reader")
             .field("reader", &self.inner)
@@ -129,7 +175,7 @@ impl fmt::Debug for Reader {
     }
 }
 
-impl Seek for Reader {
+impl<R: Seek> Seek for Reader<R> {
     /// Seek to an offset, in bytes, in the underlying reader.
     ///
     /// The position used for seeking with `SeekFrom::Current(_)` is the
@@ -179,8 +225,3 @@ impl Seek for Reader {
     }
 }
 
-#[derive(Debug)]
-pub struct DecodeResult {
-  pub result: Option<u64>,
-  pub bytes_read: usize,
-}
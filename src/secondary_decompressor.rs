@@ -0,0 +1,213 @@
+use djw_decoder;
+use fgk_decoder::FgkDecoder;
+#[cfg(all(feature = "lzma-sys", not(feature = "pure-rust-lzma")))]
+use lzma_action::LzmaAction;
+#[cfg(all(feature = "lzma-sys", not(feature = "pure-rust-lzma")))]
+use lzma_stream_wrapper::LzmaStreamWrapper;
+#[cfg(feature = "pure-rust-lzma")]
+use lzma_rust2::XzReader;
+#[cfg(feature = "pure-rust-lzma")]
+use lzma_error::LzmaError;
+use xdelta_error::XdeltaError;
+
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(feature = "pure-rust-lzma")]
+use std::io::{self, Read};
+use std::sync::Arc;
+
+/// Compressor id of xdelta3's DJW static Huffman coder
+pub const DJW_ID: u8 = 1;
+/// Compressor id of xdelta3's LZMA coder
+pub const LZMA_ID: u8 = 2;
+/// Compressor id of xdelta3's FGK adaptive Huffman coder
+pub const FGK_ID: u8 = 16;
+
+/**
+* Undoes the secondary compression of the data, instructions or addresses section of a window.
+* One instance is created per section kind and patch, so state can be carried from one window to the next.
+*/
+pub trait SecondaryDecompressor: Send {
+  /// Decompresses `input` into `output`, which has exactly the size announced by the patch
+  fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), XdeltaError>;
+}
+
+/// Creates a fresh decompressor
+pub type SecondaryFactory = Arc<dyn Fn() -> Result<Box<dyn SecondaryDecompressor>, XdeltaError> + Send + Sync>;
+
+/**
+* The secondary decompressors available to the decoder, keyed by the compressor id of the VCDIFF header.
+* `SecondaryRegistry::default()` knows DJW, FGK and, with the `lzma-sys` or `pure-rust-lzma` feature, LZMA.
+* `register` adds or replaces an id.
+*/
+#[derive(Clone)]
+pub struct SecondaryRegistry {
+  factories: BTreeMap<u8, SecondaryFactory>,
+}
+
+impl SecondaryRegistry {
+  /// A registry without any decompressor, patches with secondary compression are rejected
+  pub fn empty() -> SecondaryRegistry {
+    SecondaryRegistry { factories: BTreeMap::new() }
+  }
+
+  pub fn register<F>(&mut self, compressor_id: u8, factory: F) -> &mut SecondaryRegistry
+    where F: Fn() -> Result<Box<dyn SecondaryDecompressor>, XdeltaError> + Send + Sync + 'static {
+    self.factories.insert(compressor_id, Arc::new(factory));
+    self
+  }
+
+  pub fn contains(&self, compressor_id: u8) -> bool {
+    self.factories.contains_key(&compressor_id)
+  }
+
+  pub fn create(&self, compressor_id: u8) -> Result<Box<dyn SecondaryDecompressor>, XdeltaError> {
+    match self.factories.get(&compressor_id) {
+      Some(factory) => factory(),
+      None => Err(XdeltaError::UnsupportedSecondaryCompressor(compressor_id)),
+    }
+  }
+}
+
+impl Default for SecondaryRegistry {
+  fn default() -> SecondaryRegistry {
+    let mut registry = SecondaryRegistry::empty();
+    registry.register(DJW_ID, || Ok(Box::new(DjwDecompressor)));
+    #[cfg(any(feature = "lzma-sys", feature = "pure-rust-lzma"))]
+    registry.register(LZMA_ID, || Ok(Box::new(LzmaDecompressor::new()?)));
+    registry.register(FGK_ID, || Ok(Box::new(FgkDecoder::new())));
+    registry
+  }
+}
+
+impl fmt::Debug for SecondaryRegistry {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_set().entries(self.factories.keys()).finish()
+  }
+}
+
+/// DJW codes every section on its own, there is no state
+struct DjwDecompressor;
+
+impl SecondaryDecompressor for DjwDecompressor {
+  fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), XdeltaError> {
+    djw_decoder::decode(input, output)
+  }
+}
+
+impl SecondaryDecompressor for FgkDecoder {
+  fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), XdeltaError> {
+    self.decode(input, output)
+  }
+}
+
+/// xdelta3 keeps one xz stream per section kind running over all windows
+#[cfg(all(feature = "lzma-sys", not(feature = "pure-rust-lzma")))]
+struct LzmaDecompressor {
+  stream: LzmaStreamWrapper,
+}
+
+#[cfg(all(feature = "lzma-sys", not(feature = "pure-rust-lzma")))]
+impl LzmaDecompressor {
+  fn new() -> Result<LzmaDecompressor, XdeltaError> {
+    let mut stream = LzmaStreamWrapper::new();
+    stream.stream_decoder(u64::MAX, 0)?;
+    Ok(LzmaDecompressor { stream })
+  }
+}
+
+#[cfg(all(feature = "lzma-sys", not(feature = "pure-rust-lzma")))]
+impl SecondaryDecompressor for LzmaDecompressor {
+  fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), XdeltaError> {
+    let result = self.stream.code(input, output, LzmaAction::LzmaRun);
+    result.ret?;
+    if result.bytes_written != output.len() {
+      return Err(XdeltaError::InvalidWindow("secondary section is shorter than announced"));
+    }
+    Ok(())
+  }
+}
+
+/// Compressed bytes handed to the pure-Rust xz reader, which only pulls as much as it needs
+#[cfg(feature = "pure-rust-lzma")]
+struct SectionInput {
+  bytes: Vec<u8>,
+  pos: usize,
+}
+
+#[cfg(feature = "pure-rust-lzma")]
+impl Read for SectionInput {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let len = buf.len().min(self.bytes.len() - self.pos);
+    buf[..len].copy_from_slice(&self.bytes[self.pos..self.pos + len]);
+    self.pos += len;
+    Ok(len)
+  }
+}
+
+/// xdelta3 keeps one xz stream per section kind running over all windows,
+/// every section continues it where the previous one stopped
+#[cfg(feature = "pure-rust-lzma")]
+struct LzmaDecompressor {
+  reader: XzReader<SectionInput>,
+}
+
+#[cfg(feature = "pure-rust-lzma")]
+impl LzmaDecompressor {
+  fn new() -> Result<LzmaDecompressor, XdeltaError> {
+    Ok(LzmaDecompressor { reader: XzReader::new(SectionInput { bytes: Vec::new(), pos: 0 }, false) })
+  }
+}
+
+#[cfg(feature = "pure-rust-lzma")]
+impl SecondaryDecompressor for LzmaDecompressor {
+  fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), XdeltaError> {
+    let pending = self.reader.inner_mut();
+    pending.bytes.drain(..pending.pos);
+    pending.pos = 0;
+    pending.bytes.extend_from_slice(input);
+    match self.reader.read_exact(output) {
+      Ok(()) => Ok(()),
+      Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => Err(XdeltaError::InvalidWindow("secondary section is shorter than announced")),
+      Err(ref err) if err.kind() == io::ErrorKind::InvalidData || err.kind() == io::ErrorKind::InvalidInput => Err(LzmaError::Data.into()),
+      Err(err) => Err(LzmaError::Io(err).into()),
+    }
+  }
+}
+
+#[cfg(all(test, any(feature = "lzma-sys", feature = "pure-rust-lzma")))]
+mod tests {
+  use super::{SecondaryRegistry, LZMA_ID};
+  use lzma_rust2::{CheckType, XzOptions, XzWriter};
+  use std::io::Write;
+  use std::num::NonZeroU64;
+
+  #[test]
+  fn lzma_stream_continues_across_sections() {
+    let first = b"first section, first section, first section".to_vec();
+    let second = b"second section continues the stream of the first section".to_vec();
+
+    // one xz block per section, so the first section's bytes are complete before the second is written
+    let mut options = XzOptions::with_preset(6);
+    options.set_check_sum_type(CheckType::None);
+    options.set_block_size(NonZeroU64::new(first.len() as u64));
+    let mut writer = XzWriter::new(Vec::new(), options).unwrap();
+    writer.write_all(&first).unwrap();
+    writer.flush().unwrap();
+    let split = writer.inner().len();
+    writer.write_all(&second).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    let mut lzma = SecondaryRegistry::default().create(LZMA_ID).unwrap();
+    let mut output = vec![0; first.len()];
+    lzma.decompress(&compressed[..split], &mut output).unwrap();
+    assert_eq!(output, first);
+    let mut output = vec![0; second.len()];
+    lzma.decompress(&compressed[split..], &mut output).unwrap();
+    assert_eq!(output, second);
+
+    let mut lzma = SecondaryRegistry::default().create(LZMA_ID).unwrap();
+    let mut output = vec![0; first.len() + 1];
+    assert!(lzma.decompress(&compressed[..split], &mut output).is_err());
+  }
+}
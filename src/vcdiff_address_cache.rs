@@ -1,4 +1,5 @@
-use std::io;
+use xdelta_error::XdeltaError;
+use encode_base7_int;
 
 static VCD_SELF: u8 = 0x00;
 static VCD_HERE: u8 = 0x01;
@@ -20,39 +21,44 @@ impl AddressCache {
     }
 
     pub fn update(&mut self, addr: u64) {
-        self.near[self.next_slot] = addr;
-        self.next_slot = (self.next_slot + 1) % self.near.len();
-        let same_len = self.same.len() as u64;
-        self.same[(addr % same_len) as usize] = addr;
+        // application-defined code tables may turn off either cache
+        if !self.near.is_empty() {
+            self.near[self.next_slot] = addr;
+            self.next_slot = (self.next_slot + 1) % self.near.len();
+        }
+        if !self.same.is_empty() {
+            let same_len = self.same.len() as u64;
+            self.same[(addr % same_len) as usize] = addr;
+        }
     }
 
-    pub fn decode<'a>(&mut self, here: u64, mode: u8, input: &'a [u8] ) -> Result<(&'a [u8], u64), io::Error> {
-        fn varint<'a>(input: &'a [u8]) -> Result<(&'a [u8], u64), io::Error> {
+    pub fn decode<'a>(&mut self, here: u64, mode: u8, input: &'a [u8] ) -> Result<(&'a [u8], u64), XdeltaError> {
+        fn varint(input: &[u8]) -> Result<(&[u8], u64), XdeltaError> {
             let mut result : u64 = 0;
             let mut not_finished : bool = true;
             let mut counter = 0;
             while not_finished {
-                if counter == 10 || counter == (input.len() + 1) {
-                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "unable to get instruction address"));
+                if counter == 10 || counter == input.len() {
+                    return Err(XdeltaError::TruncatedVarint);
                 }
                 let next_byte = input[counter];
                 counter += 1;
+                if result >> 57 != 0 {
+                    return Err(XdeltaError::TruncatedVarint); // doesn't fit in 64 bits
+                }
                 result = (result << 7) | (next_byte as u64 & 127);
                 if (next_byte & 128) == 0 {
                     not_finished = false;
                 }
             }
-            return Ok((&input[counter..], result));
+            Ok((&input[counter..], result))
         }
 
-        fn one<'a>(input: &'a [u8]) -> Result<(&'a [u8], u64), io::Error> {
-            if input.len() > 0 {
+        fn one(input: &[u8]) -> Result<(&[u8], u64), XdeltaError> {
+            if !input.is_empty() {
                 Ok((&input[1..], input[0] as u64))
             } else {
-                Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "unable to get instruction address",
-                ))
+                Err(XdeltaError::InvalidWindow("unable to get instruction address"))
             }
         }
 
@@ -61,17 +67,50 @@ impl AddressCache {
             res = varint(input)?;
         } else if mode == VCD_HERE {
             res = varint(input)?;
-            res.1 = here - res.1;
+            res.1 = match here.checked_sub(res.1) {
+                Some(addr) => addr,
+                None => return Err(XdeltaError::InvalidWindow("HERE address points before the source segment")),
+            };
         } else if mode >= 2 && (mode as usize) - 2 < self.near.len() {
             res = varint(input)?;
-            res.1 = self.near[(mode as usize) - 2] + res.1;
-        } else {
+            res.1 = match res.1.checked_add(self.near[(mode as usize) - 2]) {
+                Some(addr) => addr,
+                None => return Err(XdeltaError::InvalidWindow("NEAR address overflows")),
+            };
+        } else if (mode as usize) - 2 - self.near.len() < self.same.len() / 256 {
             res = one(input)?;
             let m = (mode as usize) - 2 - self.near.len();
             res.1 = self.same[m * 256 + res.1 as usize];
+        } else {
+            return Err(XdeltaError::InvalidWindow("copy mode exceeds the address cache"));
         }
 
         self.update(res.1);
         Ok(res)
     }
+
+    /// Picks the mode that encodes `addr` in the fewest bytes, writes those bytes to `output`
+    /// and returns the mode. Mirrors `decode`, so both sides keep the same cache state.
+    pub fn encode(&mut self, here: u64, addr: u64, output: &mut Vec<u8>) -> u8 {
+        let same_slot = (addr % (self.same.len() as u64).max(1)) as usize;
+        if !self.same.is_empty() && self.same[same_slot] == addr {
+            self.update(addr);
+            output.push((same_slot % 256) as u8);
+            return (2 + self.near.len() + same_slot / 256) as u8;
+        }
+
+        let mut best = (VCD_SELF, addr);
+        if here - addr < best.1 {
+            best = (VCD_HERE, here - addr);
+        }
+        for (i, &near) in self.near.iter().enumerate() {
+            if addr >= near && addr - near < best.1 {
+                best = ((i + 2) as u8, addr - near);
+            }
+        }
+
+        self.update(addr);
+        encode_base7_int(best.1, output);
+        best.0
+    }
 }
@@ -1,13 +1,19 @@
 use std::fmt;
+use xdelta_error::XdeltaError;
 
-#[derive(Debug, Copy, Clone)]
+/// Length of the string representation of a code table, RFC 3284 section 7
+pub const CODE_TABLE_STRING_LENGTH: usize = 6 * 256;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum InstructionType {
+    /// Only used for entries of application-defined tables that do nothing
+    Noop,
     Add,
     Run,
     Copy,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Instruction {
     pub typ: InstructionType,
     pub size: u8,
@@ -16,6 +22,8 @@ pub struct Instruction {
 
 pub struct CodeTable {
     pub entries: [(Instruction, Option<Instruction>); 256],
+    pub near_cache_size: u8,
+    pub same_cache_size: u8,
 }
 
 
@@ -45,7 +53,7 @@ impl CodeTable {
         for size in 0..18 {
             vec[idx].0 = Instruction {
                 typ: InstructionType::Add,
-                size: size,
+                size,
                 mode: 0,
             };
             idx += 1;
@@ -56,14 +64,14 @@ impl CodeTable {
             vec[idx].0 = Instruction {
                 typ: InstructionType::Copy,
                 size: 0,
-                mode: mode,
+                mode,
             };
             idx += 1;
             for size in 4..19 {
                 vec[idx].0 = Instruction {
                     typ: InstructionType::Copy,
-                    size: size,
-                    mode: mode,
+                    size,
+                    mode,
                 };
                 idx += 1;
             }
@@ -82,7 +90,7 @@ impl CodeTable {
                         Some(Instruction {
                             typ: InstructionType::Copy,
                             size: copy_size,
-                            mode: mode,
+                            mode,
                         }),
                     );
                     idx += 1;
@@ -102,7 +110,7 @@ impl CodeTable {
                     Some(Instruction {
                         typ: InstructionType::Copy,
                         size: 4,
-                        mode: mode,
+                        mode,
                     }),
                 );
                 idx += 1;
@@ -115,7 +123,7 @@ impl CodeTable {
                 Instruction {
                     typ: InstructionType::Copy,
                     size: 4,
-                    mode: mode,
+                    mode,
                 },
                 Some(Instruction {
                     typ: InstructionType::Add,
@@ -126,6 +134,81 @@ impl CodeTable {
             idx += 1;
         }
 
-        CodeTable { entries: vec }
+        CodeTable { entries: vec, near_cache_size: 4, same_cache_size: 3 }
+    }
+
+    /// The string representation of RFC 3284 section 7: the instruction types, sizes and modes
+    /// of the first and second instruction of every entry, as six arrays of 256 bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn typ(inst: Option<Instruction>) -> u8 {
+            match inst.map(|inst| inst.typ) {
+                None | Some(InstructionType::Noop) => 0,
+                Some(InstructionType::Add) => 1,
+                Some(InstructionType::Run) => 2,
+                Some(InstructionType::Copy) => 3,
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(CODE_TABLE_STRING_LENGTH);
+        bytes.extend(self.entries.iter().map(|e| typ(Some(e.0))));
+        bytes.extend(self.entries.iter().map(|e| typ(e.1)));
+        bytes.extend(self.entries.iter().map(|e| e.0.size));
+        bytes.extend(self.entries.iter().map(|e| e.1.map_or(0, |inst| inst.size)));
+        bytes.extend(self.entries.iter().map(|e| e.0.mode));
+        bytes.extend(self.entries.iter().map(|e| e.1.map_or(0, |inst| inst.mode)));
+        bytes
+    }
+
+    /// Parses the string representation written by `to_bytes`, checking that every COPY mode
+    /// exists for the given cache sizes
+    pub fn from_bytes(near_cache_size: u8, same_cache_size: u8, bytes: &[u8]) -> Result<CodeTable, XdeltaError> {
+        if bytes.len() != CODE_TABLE_STRING_LENGTH {
+            return Err(XdeltaError::InvalidCodeTable("code table string must be 1536 bytes"));
+        }
+        let modes = 2 + near_cache_size as usize + same_cache_size as usize;
+        let instruction = |idx: usize, half: usize| -> Result<Option<Instruction>, XdeltaError> {
+            let typ = match bytes[half * 256 + idx] {
+                0 => return Ok(None),
+                1 => InstructionType::Add,
+                2 => InstructionType::Run,
+                3 => InstructionType::Copy,
+                _ => return Err(XdeltaError::InvalidCodeTable("unknown instruction type")),
+            };
+            let mode = bytes[(4 + half) * 256 + idx];
+            if typ == InstructionType::Copy && mode as usize >= modes {
+                return Err(XdeltaError::InvalidCodeTable("copy mode exceeds the address cache"));
+            }
+            Ok(Some(Instruction { typ, size: bytes[(2 + half) * 256 + idx], mode }))
+        };
+
+        let mut table = CodeTable::default();
+        table.near_cache_size = near_cache_size;
+        table.same_cache_size = same_cache_size;
+        for idx in 0..256 {
+            table.entries[idx] = match (instruction(idx, 0)?, instruction(idx, 1)?) {
+                (Some(first), second) => (first, second),
+                (None, Some(second)) => (second, None),
+                (None, None) => (Instruction { typ: InstructionType::Noop, size: 0, mode: 0 }, None),
+            };
+        }
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CodeTable, CODE_TABLE_STRING_LENGTH};
+
+    #[test]
+    fn string_representation() {
+        let bytes = CodeTable::default().to_bytes();
+        assert_eq!(bytes.len(), CODE_TABLE_STRING_LENGTH);
+        // entry 0 is RUN size 0, entry 1 ADD size 0, entry 255 is COPY size 4 mode 8 + ADD size 1
+        assert_eq!((bytes[0], bytes[1], bytes[255], bytes[256 + 255]), (2, 1, 3, 1));
+        assert_eq!((bytes[512 + 255], bytes[768 + 255], bytes[1024 + 255]), (4, 1, 8));
+
+        let parsed = CodeTable::from_bytes(4, 3, &bytes).unwrap();
+        assert_eq!(&parsed.entries[..], &CodeTable::default().entries[..]);
+        assert!(CodeTable::from_bytes(4, 2, &bytes).is_err());
     }
 }
@@ -0,0 +1,281 @@
+use vcdiff_address_cache::AddressCache;
+use vcdiff_code_table::{InstructionType,Instruction,CodeTable};
+use vcdiff_rolling_hash::RollingHash;
+use adler32::adler32;
+use encode_base7_int;
+
+/// Bytes hashed per match candidate, also the shortest COPY the encoder emits
+const MATCH_LENGTH: usize = 8;
+/// Shortest run of a single byte that is emitted as RUN instead of ADD
+const MIN_RUN_LENGTH: usize = 4;
+/// The target is split into windows of at most this many bytes
+const TARGET_WINDOW_SIZE: usize = 1 << 23;
+/// Upper bound on the hash table size, RollingHash values are below 2^23 anyway
+const MAX_TABLE_SIZE: usize = 1 << 23;
+
+/// Lookup table from rolling hash to the last position (+1) that produced it, 0 means empty
+struct MatchTable {
+  slots: Vec<usize>,
+  mask: usize,
+}
+
+impl MatchTable {
+  fn new(positions: usize) -> MatchTable {
+    let size = positions.next_power_of_two().clamp(1, MAX_TABLE_SIZE);
+    MatchTable {
+      slots: vec![0; size],
+      mask: size - 1,
+    }
+  }
+
+  fn insert(&mut self, hash: u32, pos: usize) {
+    self.slots[hash as usize & self.mask] = pos + 1;
+  }
+
+  fn get(&self, hash: u32) -> Option<usize> {
+    self.slots[hash as usize & self.mask].checked_sub(1)
+  }
+}
+
+/// Buffers the three sections of a window and picks code table entries for its instructions
+struct WindowEncoder<'a> {
+  code_table: &'a CodeTable,
+  address_cache: AddressCache,
+  source_length: u64,
+  target_length: u64,
+  data: Vec<u8>,
+  instructions: Vec<u8>,
+  addresses: Vec<u8>,
+  /// The last instruction, held back in case it can share a code table entry with the next one
+  pending: Option<Instruction>,
+}
+
+impl<'a> WindowEncoder<'a> {
+  fn new(code_table: &'a CodeTable, source_length: u64) -> WindowEncoder<'a> {
+    WindowEncoder {
+      code_table,
+      address_cache: AddressCache::new(code_table.near_cache_size as usize, code_table.same_cache_size as usize),
+      source_length,
+      target_length: 0,
+      data: Vec::new(),
+      instructions: Vec::new(),
+      addresses: Vec::new(),
+      pending: None,
+    }
+  }
+
+  fn add(&mut self, bytes: &[u8]) {
+    if bytes.is_empty() {
+      return;
+    }
+    self.data.extend_from_slice(bytes);
+    self.push(InstructionType::Add, bytes.len(), 0);
+  }
+
+  fn run(&mut self, byte: u8, size: usize) {
+    self.data.push(byte);
+    self.push(InstructionType::Run, size, 0);
+  }
+
+  /// `addr` is in the address space of the window: source segment first, then the target window
+  fn copy(&mut self, addr: u64, size: usize) {
+    let here = self.source_length + self.target_length;
+    let mode = self.address_cache.encode(here, addr, &mut self.addresses);
+    self.push(InstructionType::Copy, size, mode);
+  }
+
+  fn push(&mut self, typ: InstructionType, size: usize, mode: u8) {
+    self.target_length += size as u64;
+    // sizes that don't fit in a code table entry are stored as 0 and written after the instruction byte
+    let inst = Instruction { typ, size: if size <= u8::MAX as usize { size as u8 } else { 0 }, mode };
+    if let Some(first) = self.pending.take() {
+      if let Some(index) = self.pair_index(first, inst) {
+        self.instructions.push(index);
+        return;
+      }
+      self.emit_single(first, first.size as usize);
+    }
+    if inst.size == 0 {
+      self.emit_single(inst, size);
+    } else {
+      self.pending = Some(inst);
+    }
+  }
+
+  fn pair_index(&self, first: Instruction, second: Instruction) -> Option<u8> {
+    self.code_table.entries.iter().position(|entry| entry.0 == first && entry.1 == Some(second)).map(|index| index as u8)
+  }
+
+  fn emit_single(&mut self, inst: Instruction, size: usize) {
+    let exact = self.code_table.entries.iter().position(|entry| entry.0 == inst && entry.1.is_none());
+    match exact.filter(|_| inst.size != 0) {
+      Some(index) => self.instructions.push(index as u8),
+      None => {
+        let generic = Instruction { size: 0, ..inst };
+        let index = self.code_table.entries.iter().position(|entry| entry.0 == generic && entry.1.is_none())
+          .expect("the default code table has a size 0 entry for every instruction");
+        self.instructions.push(index as u8);
+        encode_base7_int(size as u64, &mut self.instructions);
+      }
+    }
+  }
+
+  fn finish(mut self, source_segment: Option<(u64, u64)>, checksum: u32, output: &mut Vec<u8>) {
+    if let Some(inst) = self.pending.take() {
+      self.emit_single(inst, inst.size as usize);
+    }
+
+    let mut delta = Vec::new();
+    encode_base7_int(self.target_length, &mut delta);
+    delta.push(0); //delta_indicator, no secondary compression
+    encode_base7_int(self.data.len() as u64, &mut delta);
+    encode_base7_int(self.instructions.len() as u64, &mut delta);
+    encode_base7_int(self.addresses.len() as u64, &mut delta);
+    delta.extend_from_slice(&checksum.to_be_bytes());
+    delta.extend_from_slice(&self.data);
+    delta.extend_from_slice(&self.instructions);
+    delta.extend_from_slice(&self.addresses);
+
+    match source_segment {
+      Some((length, position)) => {
+        output.push(1 | 4); //VCD_SOURCE | VCD_ADLER32
+        encode_base7_int(length, output);
+        encode_base7_int(position, output);
+      },
+      None => output.push(4), //VCD_ADLER32
+    }
+    encode_base7_int(delta.len() as u64, output);
+    output.extend_from_slice(&delta);
+  }
+}
+
+fn match_length(a: &[u8], b: &[u8]) -> usize {
+  a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+/**
+* Creates a VCDIFF patch that turns `source` into `target`.
+* Matches are found with a RollingHash over the source and the already encoded part of each target window.
+*/
+pub fn encode(source: &[u8], target: &[u8]) -> Vec<u8> {
+  let hasher = RollingHash::new(MATCH_LENGTH);
+  let width = hasher.window_size();
+  let code_table = CodeTable::default();
+
+  let mut source_table = MatchTable::new(source.len());
+  if source.len() >= width {
+    let mut hash = hasher.hash(&source[..width]);
+    source_table.insert(hash, 0);
+    for pos in 1..=source.len() - width {
+      hash = hasher.shift(hash, source[pos - 1], source[pos + width - 1]);
+      source_table.insert(hash, pos);
+    }
+  }
+  let source_segment = if source.is_empty() { None } else { Some((source.len() as u64, 0)) };
+
+  let mut output = vec![0xD6, 0xC3, 0xC4, 0x00, 0x00];
+  for window in target.chunks(TARGET_WINDOW_SIZE) {
+    let mut encoder = WindowEncoder::new(&code_table, source.len() as u64);
+    let mut target_table = MatchTable::new(window.len());
+    let mut hash = None;
+    let mut add_start = 0;
+    let mut pos = 0;
+    while pos + width <= window.len() {
+      let current = match hash {
+        Some(h) => hasher.shift(h, window[pos - 1], window[pos + width - 1]),
+        None => hasher.hash(&window[pos..pos + width]),
+      };
+      hash = Some(current);
+
+      // longest of: a run, a copy from the source, a copy from earlier in this window
+      let run = 1 + match_length(&window[pos..], &window[pos + 1..]);
+      let mut best : Option<(u64, usize)> = None;
+      if let Some(candidate) = source_table.get(current) {
+        let length = match_length(&source[candidate..], &window[pos..]);
+        if length >= width {
+          best = Some((candidate as u64, length));
+        }
+      }
+      if let Some(candidate) = target_table.get(current) {
+        let length = match_length(&window[candidate..], &window[pos..]);
+        if length >= width && best.is_none_or(|(_, best_length)| length > best_length) {
+          best = Some((source.len() as u64 + candidate as u64, length));
+        }
+      }
+      target_table.insert(current, pos);
+
+      match best {
+        Some((mut addr, mut length)) if length >= run || run < MIN_RUN_LENGTH => {
+          // grow the copy backwards over literal bytes that match as well
+          let byte_at = |addr: u64| if addr < source.len() as u64 { source[addr as usize] } else { window[(addr - source.len() as u64) as usize] };
+          let mut start = pos;
+          while start > add_start && addr > 0 && addr != source.len() as u64 && byte_at(addr - 1) == window[start - 1] {
+            addr -= 1;
+            start -= 1;
+            length += 1;
+          }
+          encoder.add(&window[add_start..start]);
+          encoder.copy(addr, length);
+
+          // keep hashing through the copied bytes so later copies can reference them
+          let end = start + length;
+          let mut h = current;
+          for skipped in pos + 1..end.min(window.len() + 1 - width) {
+            h = hasher.shift(h, window[skipped - 1], window[skipped + width - 1]);
+            target_table.insert(h, skipped);
+          }
+          hash = Some(h);
+          pos = end;
+          add_start = end;
+        },
+        _ if run >= MIN_RUN_LENGTH => {
+          encoder.add(&window[add_start..pos]);
+          encoder.run(window[pos], run);
+          pos += run;
+          add_start = pos;
+          hash = None;
+        },
+        _ => pos += 1,
+      }
+    }
+    encoder.add(&window[add_start..]);
+    encoder.finish(source_segment, adler32(window), &mut output);
+  }
+  output
+}
+
+#[cfg(test)]
+mod tests {
+  use super::encode;
+  use decode_to_vec;
+
+  fn pseudo_random(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed;
+    (0..len).map(|_| {
+      state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+      (state >> 16) as u8
+    }).collect()
+  }
+
+  #[test]
+  fn round_trip() {
+    let source = pseudo_random(100_000, 1);
+    let mut target = source[5_000..40_000].to_vec();
+    target.extend_from_slice(&pseudo_random(3_000, 2));
+    target.extend_from_slice(&[7; 500]);
+    target.extend_from_slice(&source[60_000..]);
+    target.extend_from_within(1_000..9_000);
+    target.extend_from_slice(b"tail");
+
+    let patch = encode(&source, &target);
+    assert!(patch.len() < 4_000);
+    assert_eq!(decode_to_vec(&source, &patch).unwrap(), target);
+  }
+
+  #[test]
+  fn empty_source_and_target() {
+    let target = b"abcabcabcabcabcabcabcabc xyz xyz xyz xyz xyz".to_vec();
+    assert_eq!(decode_to_vec(&[], &encode(&[], &target)).unwrap(), target);
+    assert_eq!(decode_to_vec(b"source", &encode(b"source", &[])).unwrap(), b"");
+  }
+}
@@ -1,11 +1,30 @@
+use app_header::AppHeader;
 use reader::Reader;
-use std::io::{Seek,Read};
+use decode_options::{check_limit, DecodeLimits};
+use xdelta_error::XdeltaError;
+use std::io::Read;
 
+/// The first three bytes of every VCDIFF file, the high bit set on "VCD"
+pub const VCDIFF_MAGIC: [u8; 3] = [0xD6, 0xC3, 0xC4];
+/// VCD_SECONDARY | VCD_CODETABLE | VCD_APPHEADER, all other header indicator bits are reserved
+const HDR_INDICATOR_MASK: u8 = 0x07;
+
+/**
+* Cheap format probe: whether `bytes`, the start of a file, look like a VCDIFF patch this crate can decode.
+* Checks the magic, the version and, if present, the header indicator.
+*/
+pub fn is_vcdiff(bytes: &[u8]) -> bool {
+  bytes.len() >= 4 && bytes[0..3] == VCDIFF_MAGIC && bytes[3] == 0
+    && bytes.get(4).is_none_or(|&indicator| indicator & !HDR_INDICATOR_MASK == 0)
+}
+
+/// An application-defined code table, `compressed_data` is a VCDIFF delta against the
+/// string representation of the default code table
 #[derive(Debug)]
 pub struct CodeTable {
-  near_cache_size: u8,
-  same_cache_size: u8,
-  compressed_data: Vec<u8>
+  pub near_cache_size: u8,
+  pub same_cache_size: u8,
+  pub compressed_data: Vec<u8>
 }
 
 #[derive(Debug)]
@@ -20,41 +39,60 @@ pub struct Header {
 }
 
 impl Header {
-  pub fn new(bytes: &mut Reader) -> Header {
+  pub fn new<R: Read>(bytes: &mut Reader<R>, limits: &DecodeLimits) -> Result<Header, XdeltaError> {
     let mut header = Header {
-      header: [bytes.next().unwrap(),
-               bytes.next().unwrap(),
-               bytes.next().unwrap(),
-               bytes.next().unwrap()],
-      hdr_indicator: bytes.next().unwrap(),
+      header: [bytes.read_byte()?,
+               bytes.read_byte()?,
+               bytes.read_byte()?,
+               bytes.read_byte()?],
+      hdr_indicator: 0,
       secondary_compressor_id: None,
       code_table_length: None,
       code_table: None,
       appheader_size: None,
       appheader: Vec::new(),
     };
+    if header.header[0..3] != VCDIFF_MAGIC {
+      return Err(XdeltaError::InvalidMagic(header.header));
+    }
+    if header.header[3] != 0 {
+      return Err(XdeltaError::UnsupportedVersion(header.header[3]));
+    }
+    header.hdr_indicator = bytes.read_byte()?;
+    if header.hdr_indicator & !HDR_INDICATOR_MASK != 0 {
+      return Err(XdeltaError::ReservedBits { indicator: "header indicator", value: header.hdr_indicator });
+    }
     if header.hdr_indicator % 2 >= 1 { //VCD_SECONDARY
-      header.secondary_compressor_id = Some(bytes.next().unwrap());
+      header.secondary_compressor_id = Some(bytes.read_byte()?);
     }
     if header.hdr_indicator % 4 >= 2 { //VCD_CODETABLE
-      header.code_table_length = bytes.decode_base7_int().result;
-      let mut code_table = CodeTable{
-                                 near_cache_size: bytes.next().unwrap(),
-                                 same_cache_size: bytes.next().unwrap(),
-                                 compressed_data: Vec::with_capacity(header.code_table_length.unwrap() as usize)
+      let code_table_length = bytes.decode_base7_int()?;
+      header.code_table_length = Some(code_table_length);
+      // the length covers the two cache sizes as well
+      let compressed_length = match code_table_length.checked_sub(2) {
+        Some(length) => length,
+        None => return Err(XdeltaError::InvalidCodeTable("code table data is shorter than the cache sizes")),
+      };
+      check_limit("max_app_header", limits.max_app_header, compressed_length)?;
+      let code_table = CodeTable{
+                                 near_cache_size: bytes.read_byte()?,
+                                 same_cache_size: bytes.read_byte()?,
+                                 compressed_data: bytes.read_vec(compressed_length)?
                                };
-      bytes.seek(std::io::SeekFrom::Current(0)).unwrap();
-      code_table.compressed_data.resize(header.code_table_length.unwrap() as usize,0);
-      bytes.read(&mut code_table.compressed_data).unwrap();
       header.code_table = Some(code_table);
     }
     if header.hdr_indicator % 8 >= 4 { //VCD_APPHEADER
-      header.appheader_size = bytes.decode_base7_int().result;
-      bytes.seek(std::io::SeekFrom::Current(0)).unwrap();
-      header.appheader = Vec::with_capacity(header.appheader_size.unwrap() as usize);
-      header.appheader.resize(header.appheader_size.unwrap() as usize,0);
-      bytes.read(&mut header.appheader).unwrap();
+      let appheader_size = bytes.decode_base7_int()?;
+      header.appheader_size = Some(appheader_size);
+      check_limit("max_app_header", limits.max_app_header, appheader_size)?;
+      header.appheader = bytes.read_vec(appheader_size)?;
     }
-    header
+    Ok(header)
+  }
+
+  /// The application header in xdelta3's `name/comp/srcname/srccomp` format, if there is one
+  pub fn app_header(&self) -> Option<AppHeader> {
+    self.appheader_size?;
+    AppHeader::parse(&self.appheader)
   }
 }
@@ -0,0 +1,143 @@
+use vcdiff_address_cache::AddressCache;
+use vcdiff_code_table::{InstructionType,Instruction,CodeTable};
+use xdelta_error::XdeltaError;
+use decode_base7_int;
+
+/// One instruction of a window with its size, data and address resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation<'a> {
+  /// Appends `data` to the target window
+  Add { data: &'a [u8] },
+  /// Appends `size` copies of `byte`
+  Run { byte: u8, size: u64 },
+  /// Appends `size` bytes starting at `address`, which counts from the start of the source segment
+  /// and continues into the target window. `mode` is the address cache mode it was coded with.
+  Copy { address: u64, size: u64, mode: u8 },
+}
+
+impl<'a> Operation<'a> {
+  /// Number of target bytes the operation produces
+  pub fn size(&self) -> u64 {
+    match *self {
+      Operation::Add { data } => data.len() as u64,
+      Operation::Run { size, .. } | Operation::Copy { size, .. } => size,
+    }
+  }
+}
+
+/**
+* Walks the instructions section of a window: looks up every instruction in the code table,
+* reads explicit sizes, takes ADD and RUN bytes from the data section and resolves COPY
+* addresses through the address cache. Stops after the first error.
+*/
+pub struct Instructions<'a> {
+  code_table: &'a CodeTable,
+  data: &'a [u8],
+  instructions: &'a [u8],
+  addresses: &'a [u8],
+  address_cache: AddressCache,
+  source_length: u64,
+  target_length: u64,
+  /// the target window length from the window header, the instructions may not produce more
+  window_length: u64,
+  /// second half of the last code table entry
+  pending: Option<Instruction>,
+  failed: bool,
+}
+
+impl<'a> Instructions<'a> {
+  pub(crate) fn new(code_table: &'a CodeTable, source_length: u64, window_length: u64, data: &'a [u8], instructions: &'a [u8], addresses: &'a [u8]) -> Instructions<'a> {
+    Instructions {
+      code_table,
+      data,
+      instructions,
+      addresses,
+      address_cache: AddressCache::new(code_table.near_cache_size as usize, code_table.same_cache_size as usize),
+      source_length,
+      target_length: 0,
+      window_length,
+      pending: None,
+      failed: false,
+    }
+  }
+
+  fn next_operation(&mut self) -> Result<Option<Operation<'a>>, XdeltaError> {
+    loop {
+      let inst = match self.pending.take() {
+        Some(inst) => inst,
+        None => match self.instructions.split_first() {
+          Some((&index, rest)) => {
+            self.instructions = rest;
+            let entry = self.code_table.entries[index as usize];
+            self.pending = entry.1;
+            entry.0
+          },
+          None => return Ok(None),
+        },
+      };
+      if inst.typ == InstructionType::Noop {
+        continue;
+      }
+
+      let mut size = inst.size as u64;
+      if size == 0 {
+        let mut remaining = self.instructions.iter();
+        size = decode_base7_int(&mut remaining)?.result;
+        self.instructions = remaining.as_slice();
+      }
+      if size > self.window_length - self.target_length {
+        return Err(XdeltaError::InvalidWindow("instructions produce more than the target window length"));
+      }
+      let operation = match inst.typ {
+        InstructionType::Add => {
+          if (self.data.len() as u64) < size {
+            return Err(XdeltaError::InvalidWindow("ADD reads past the end of the data section"));
+          }
+          let (data, rest) = self.data.split_at(size as usize);
+          self.data = rest;
+          Operation::Add { data }
+        },
+        InstructionType::Run => {
+          let (&byte, rest) = match self.data.split_first() {
+            Some(split) => split,
+            None => return Err(XdeltaError::InvalidWindow("RUN reads past the end of the data section")),
+          };
+          self.data = rest;
+          Operation::Run { byte, size }
+        },
+        InstructionType::Copy => {
+          let here = match self.source_length.checked_add(self.target_length) {
+            Some(here) => here,
+            None => return Err(XdeltaError::InvalidWindow("source segment and target window are too long")),
+          };
+          let (rest, address) = self.address_cache.decode(here, inst.mode, self.addresses)?;
+          self.addresses = rest;
+          if address >= here {
+            return Err(XdeltaError::InvalidWindow("COPY address is not before the current position"));
+          }
+          Operation::Copy { address, size, mode: inst.mode }
+        },
+        InstructionType::Noop => unreachable!(),
+      };
+      self.target_length += size;
+      return Ok(Some(operation));
+    }
+  }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+  type Item = Result<Operation<'a>, XdeltaError>;
+
+  fn next(&mut self) -> Option<Result<Operation<'a>, XdeltaError>> {
+    if self.failed {
+      return None;
+    }
+    match self.next_operation() {
+      Ok(operation) => operation.map(Ok),
+      Err(err) => {
+        self.failed = true;
+        Some(Err(err))
+      },
+    }
+  }
+}
@@ -1,5 +1,3 @@
-use std::io::Read;
-
 /// a prime number
 static A_PRIME: u32 = 257;
 
@@ -24,12 +22,12 @@ impl RollingHash {
             m = (m * A_PRIME) & N_EFFICIENT;
         }
 
-        for b in 0..256 {
-            remove_table[b] = ((b as u32) * m).wrapping_neg() & N_EFFICIENT;
+        for (b, entry) in remove_table.iter_mut().enumerate() {
+            *entry = ((b as u32) * m).wrapping_neg() & N_EFFICIENT;
         }
 
         RollingHash {
-            remove_table: remove_table,
+            remove_table,
             window_size,
         }
     }
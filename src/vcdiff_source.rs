@@ -0,0 +1,102 @@
+use xdelta_error::XdeltaError;
+use std::fs::File;
+use std::io::{Read,Seek,SeekFrom};
+use std::path::Path;
+
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+
+/// The source file that COPY instructions of VCD_SOURCE windows read from
+pub trait Source {
+  /// Fills `buf` with the source bytes starting at `pos`
+  fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> Result<(), XdeltaError>;
+}
+
+/// A source that is read by seeking, e.g. a `std::fs::File`
+pub struct SeekSource<S>(pub S);
+
+impl<S: Read + Seek> Source for SeekSource<S> {
+  fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> Result<(), XdeltaError> {
+    self.0.seek(SeekFrom::Start(pos))?;
+    self.0.read_exact(buf)?;
+    Ok(())
+  }
+}
+
+/// An in-memory source, COPY instructions become plain slice copies
+impl Source for &[u8] {
+  fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> Result<(), XdeltaError> {
+    match self.get(pos as usize..).and_then(|rest| rest.get(..buf.len())) {
+      Some(bytes) => {
+        buf.copy_from_slice(bytes);
+        Ok(())
+      },
+      None => Err(XdeltaError::InvalidWindow("copy reaches past the end of the source")),
+    }
+  }
+}
+
+/**
+* The source of the `decode_file` functions. With the `mmap` feature the file is mapped into
+* memory and COPY instructions are slice copies, files that can't be mapped are read by seeking.
+* A mapped source file must not be changed while the patch is applied.
+*/
+pub enum FileSource {
+  #[cfg(feature = "mmap")]
+  Mapped(Mmap),
+  Seek(SeekSource<File>),
+}
+
+impl FileSource {
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<FileSource, XdeltaError> {
+    let file = File::open(path)?;
+    #[cfg(feature = "mmap")]
+    {
+      if let Some(map) = map_file(&file) {
+        return Ok(FileSource::Mapped(map));
+      }
+    }
+    Ok(FileSource::Seek(SeekSource(file)))
+  }
+}
+
+impl Source for FileSource {
+  fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> Result<(), XdeltaError> {
+    match *self {
+      #[cfg(feature = "mmap")]
+      FileSource::Mapped(ref map) => (&map[..]).read_at(pos, buf),
+      FileSource::Seek(ref mut file) => file.read_at(pos, buf),
+    }
+  }
+}
+
+/// Maps `file` into memory, `None` if it isn't a regular file or the mapping fails
+#[cfg(feature = "mmap")]
+pub fn map_file(file: &File) -> Option<Mmap> {
+  if !file.metadata().ok()?.is_file() {
+    return None;
+  }
+  // the caller promises that the file isn't modified while it is mapped
+  unsafe { Mmap::map(file) }.ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{FileSource, Source};
+  use tests::TempDir;
+  use std::fs;
+
+  #[test]
+  fn file_source() {
+    let dir = TempDir::new("file-source");
+    let path = dir.join("source");
+    fs::write(&path, b"0123456789").unwrap();
+    let mut source = FileSource::open(&path).unwrap();
+    #[cfg(feature = "mmap")]
+    assert!(matches!(source, FileSource::Mapped(_)));
+    let mut buf = [0; 4];
+    source.read_at(3, &mut buf).unwrap();
+    assert_eq!(&buf, b"3456");
+    assert!(source.read_at(7, &mut buf).is_err());
+  }
+}
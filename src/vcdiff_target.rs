@@ -0,0 +1,173 @@
+use xdelta_error::XdeltaError;
+use std::io::{Read,Write,Seek,SeekFrom};
+
+/// Where the decoded target goes. VCD_TARGET windows and streamed windows read earlier output back.
+pub trait Target {
+  /// Appends `buf` to the output
+  fn write_all(&mut self, buf: &[u8]) -> Result<(), XdeltaError>;
+  /// Fills `buf` with already written output starting at `pos`, relative to the start of the decoding
+  fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> Result<(), XdeltaError>;
+  /// Bytes written so far, the target position of the next byte
+  fn position(&self) -> u64;
+  /// Called after every window, when only output of earlier windows can be referenced again
+  fn end_window(&mut self) {}
+  /// Whether a window may be written in chunks before it's complete, see `DecodeOptions::stream_chunk_size`
+  fn streams_windows(&self) -> bool {
+    false
+  }
+  /// Goes back to `position`, the start of a streamed window that failed its checksum
+  fn rewind(&mut self, _position: u64) -> Result<(), XdeltaError> {
+    Ok(())
+  }
+}
+
+impl<T: Target> Target for &mut T {
+  fn write_all(&mut self, buf: &[u8]) -> Result<(), XdeltaError> {
+    (**self).write_all(buf)
+  }
+
+  fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> Result<(), XdeltaError> {
+    (**self).read_at(pos, buf)
+  }
+
+  fn position(&self) -> u64 {
+    (**self).position()
+  }
+
+  fn end_window(&mut self) {
+    (**self).end_window();
+  }
+
+  fn streams_windows(&self) -> bool {
+    (**self).streams_windows()
+  }
+
+  fn rewind(&mut self, position: u64) -> Result<(), XdeltaError> {
+    (**self).rewind(position)
+  }
+}
+
+/// A target that is read back by seeking, e.g. a `std::fs::File`
+pub struct SeekTarget<T> {
+  inner: T,
+  /// stream position the decoding started at
+  base: u64,
+  written: u64,
+}
+
+impl<T: Read + Write + Seek> SeekTarget<T> {
+  pub fn new(mut inner: T) -> Result<SeekTarget<T>, XdeltaError> {
+    Ok(SeekTarget { base: inner.stream_position()?, inner, written: 0 })
+  }
+
+  /// Continues a target of which `written` bytes, starting at the current position, are decoded already
+  pub fn resume(mut inner: T, written: u64) -> Result<SeekTarget<T>, XdeltaError> {
+    let base = inner.stream_position()?;
+    let end = match base.checked_add(written) {
+      Some(end) => end,
+      None => return Err(XdeltaError::InvalidCheckpoint("checkpoint is past the largest position")),
+    };
+    if inner.seek(SeekFrom::End(0))? < end {
+      return Err(XdeltaError::InvalidCheckpoint("target is shorter than the checkpoint"));
+    }
+    inner.seek(SeekFrom::Start(end))?;
+    Ok(SeekTarget { base, inner, written })
+  }
+}
+
+impl<T: Read + Write + Seek> Target for SeekTarget<T> {
+  fn write_all(&mut self, buf: &[u8]) -> Result<(), XdeltaError> {
+    self.inner.write_all(buf)?;
+    self.written += buf.len() as u64;
+    Ok(())
+  }
+
+  fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> Result<(), XdeltaError> {
+    if pos.checked_add(buf.len() as u64).is_none_or(|end| end > self.written) {
+      return Err(XdeltaError::InvalidWindow("copy reaches past the decoded target"));
+    }
+    self.inner.seek(SeekFrom::Start(self.base + pos))?;
+    self.inner.read_exact(buf)?;
+    self.inner.seek(SeekFrom::Start(self.base + self.written))?;
+    Ok(())
+  }
+
+  fn position(&self) -> u64 {
+    self.written
+  }
+
+  fn streams_windows(&self) -> bool {
+    true
+  }
+
+  fn rewind(&mut self, position: u64) -> Result<(), XdeltaError> {
+    self.inner.seek(SeekFrom::Start(self.base + position))?;
+    self.written = position;
+    Ok(())
+  }
+}
+
+/**
+* A target for any `Write` sink, which keeps what it emitted in memory so it can be copied again.
+* Only the last `limit` bytes of earlier windows are kept, plus the current window. Without a
+* limit, as many bytes are kept as the largest window so far produced.
+*/
+pub struct HistoryTarget<W> {
+  inner: W,
+  history: Vec<u8>,
+  /// target position of `history[0]`
+  history_start: u64,
+  limit: Option<usize>,
+  /// target position where the current window started
+  window_start: u64,
+  largest_window: usize,
+}
+
+impl<W: Write> HistoryTarget<W> {
+  pub fn new(inner: W, limit: Option<usize>) -> HistoryTarget<W> {
+    HistoryTarget { inner, history: Vec::new(), history_start: 0, limit, window_start: 0, largest_window: 0 }
+  }
+
+  #[cfg(feature = "async")]
+  pub fn get_mut(&mut self) -> &mut W {
+    &mut self.inner
+  }
+}
+
+impl<W: Write> Target for HistoryTarget<W> {
+  fn write_all(&mut self, buf: &[u8]) -> Result<(), XdeltaError> {
+    self.inner.write_all(buf)?;
+    self.history.extend_from_slice(buf);
+    Ok(())
+  }
+
+  fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> Result<(), XdeltaError> {
+    if pos < self.history_start {
+      return Err(XdeltaError::InvalidWindow("copy reaches before the retained target history"));
+    }
+    let offset = (pos - self.history_start) as usize;
+    match self.history.get(offset..).and_then(|rest| rest.get(..buf.len())) {
+      Some(bytes) => {
+        buf.copy_from_slice(bytes);
+        Ok(())
+      },
+      None => Err(XdeltaError::InvalidWindow("copy reaches past the decoded target")),
+    }
+  }
+
+  fn position(&self) -> u64 {
+    self.history_start + self.history.len() as u64
+  }
+
+  fn end_window(&mut self) {
+    let position = self.position();
+    self.largest_window = self.largest_window.max((position - self.window_start) as usize);
+    self.window_start = position;
+    let limit = self.limit.unwrap_or(self.largest_window);
+    if self.history.len() > limit {
+      let dropped = self.history.len() - limit;
+      self.history.drain(..dropped);
+      self.history_start += dropped as u64;
+    }
+  }
+}
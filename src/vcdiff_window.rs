@@ -1,18 +1,23 @@
-use vcdiff_address_cache::AddressCache;
-use vcdiff_code_table::{InstructionType,Instruction,CodeTable};
-use std::io::{Read,Write,Seek};
+use vcdiff_code_table::CodeTable;
+use vcdiff_instructions::{Instructions, Operation};
+use std::io::Read;
 use reader::Reader;
+use vcdiff_source::Source;
+use vcdiff_target::Target;
+use decode_options::{check_limit, DecodeLimits, DecodeOptions};
+use adler32::Adler32;
+use xdelta_error::XdeltaError;
 
 pub struct Window {
-  window_indicator: u8, //VCD_SOURCE, VCD_TARGET, VCD_ADLER32
-  source_segment: Option<(u64,u64)>, //unimplemented behavior
-  delta_encoding_length: u64, // size/length of the entire struct
+  pub window_indicator: u8, //VCD_SOURCE, VCD_TARGET, VCD_ADLER32
+  pub source_segment: Option<(u64,u64)>, //length and position of the segment
+  pub delta_encoding_length: u64, // size/length of the entire struct
   pub target_window_length: u64, // size of ??
   pub delta_indicator: u8,
   pub data_length: u64,
   pub instructions_length: u64,
   pub addresses_length: u64,
-  adler32_checksum: Option<[u8;4]>,
+  pub adler32_checksum: Option<[u8;4]>,
   pub data: Vec<u8>,
   pub instructions: Vec<u8>,
   pub addresses: Vec<u8>,
@@ -29,7 +34,7 @@ impl std::fmt::Debug for Window {
      .field("data_length", &self.data_length)
      .field("instructions_length", &self.instructions_length)
      .field("addresses_length", &self.addresses_length)
-     .field("d.i.a_length", &(&self.data_length + &self.instructions_length + &self.addresses_length))
+     .field("d.i.a_length", &(self.data_length + self.instructions_length + self.addresses_length))
      .finish()
   }
 }
@@ -38,9 +43,9 @@ impl Window {
   /**
   * Creates a new Window instance and uses an iterator to fill it with the data of a vcdiff
   */
-  pub fn new(bytes: &mut Reader) -> Window {
+  pub fn new<R: Read>(bytes: &mut Reader<R>, limits: &DecodeLimits) -> Result<Window, XdeltaError> {
     let mut window = Window {
-      window_indicator: bytes.next().unwrap(), //1 byte
+      window_indicator: bytes.read_byte()?, //1 byte
       source_segment: None,  //up to 20 bytes
       delta_encoding_length: 0, //up to 10 bytes
       target_window_length: 0, //up to 10 bytes
@@ -53,133 +58,250 @@ impl Window {
       instructions: Vec::new(),
       addresses: Vec::new(),
     };
+    check_window_indicator(window.window_indicator)?;
     if window.window_indicator % 2 >= 1 || window.window_indicator % 4 >= 2 { //VCD_SOURCE || VCD_TARGET
-      window.source_segment = Some((bytes.decode_base7_int().result.unwrap(), bytes.decode_base7_int().result.unwrap()));
-    }
-    window.delta_encoding_length = bytes.decode_base7_int().result.unwrap();
-    window.target_window_length = bytes.decode_base7_int().result.unwrap();
-    window.delta_indicator = bytes.next().unwrap();
-    window.data_length = bytes.decode_base7_int().result.unwrap();
-    window.instructions_length = bytes.decode_base7_int().result.unwrap();
-    window.addresses_length = bytes.decode_base7_int().result.unwrap();
+      let (length, position) = (bytes.decode_base7_int()?, bytes.decode_base7_int()?);
+      if position.checked_add(length).is_none() {
+        return Err(XdeltaError::InvalidWindow("source segment ends past the largest position"));
+      }
+      window.source_segment = Some((length, position));
+    }
+    window.delta_encoding_length = bytes.decode_base7_int()?;
+    check_limit("max_window_size", limits.max_window_size, window.delta_encoding_length)?;
+    let delta_start = bytes.offset();
+    window.target_window_length = bytes.decode_base7_int()?;
+    check_limit("max_window_size", limits.max_window_size, window.target_window_length)?;
+    window.delta_indicator = bytes.read_byte()?;
+    if window.delta_indicator & !0x07 != 0 { //VCD_DATACOMP, VCD_INSTCOMP, VCD_ADDRCOMP
+      return Err(XdeltaError::ReservedBits { indicator: "delta indicator", value: window.delta_indicator });
+    }
+    window.data_length = bytes.decode_base7_int()?;
+    window.instructions_length = bytes.decode_base7_int()?;
+    window.addresses_length = bytes.decode_base7_int()?;
     if window.window_indicator % 8 >= 4 { //VCD_ADLER32
-      window.adler32_checksum = Some([bytes.next().unwrap(),
-                          bytes.next().unwrap(),
-                          bytes.next().unwrap(),
-                          bytes.next().unwrap()]);
+      window.adler32_checksum = Some([bytes.read_byte()?,
+                          bytes.read_byte()?,
+                          bytes.read_byte()?,
+                          bytes.read_byte()?]);
+    }
+    // the delta encoding is the rest of the window header and the three sections, exactly
+    let length = window.data_length.checked_add(window.instructions_length)
+      .and_then(|length| length.checked_add(window.addresses_length))
+      .and_then(|length| length.checked_add(bytes.offset() - delta_start));
+    if length != Some(window.delta_encoding_length) {
+      return Err(XdeltaError::InvalidWindow("delta encoding length doesn't match the window"));
     }
 
     // Data bytes
-    bytes.seek(std::io::SeekFrom::Current(0)).unwrap();
-    window.data = Vec::with_capacity(1 + window.data_length as usize);
-    window.data.resize(window.data_length as usize, 0);
-    bytes.read(&mut window.data).unwrap();
+    window.data = bytes.read_vec(window.data_length)?;
 
     // Instructions bytes
-    bytes.seek(std::io::SeekFrom::Current(0)).unwrap();
-    window.instructions = Vec::with_capacity(window.instructions_length as usize);
-    window.instructions.resize(window.instructions_length as usize, 0);
-    bytes.read(&mut window.instructions).unwrap();
+    window.instructions = bytes.read_vec(window.instructions_length)?;
 
     // Addresses bytes
-    bytes.seek(std::io::SeekFrom::Current(0)).unwrap();
-    window.addresses = Vec::with_capacity(window.addresses_length as usize);
-    window.addresses.resize(window.addresses_length as usize, 0);
-    bytes.read(&mut window.addresses).unwrap();
+    window.addresses = bytes.read_vec(window.addresses_length)?;
 
     //return window
-    window
+    Ok(window)
   }
 
-  pub fn decode_window(self, original: &mut Option<std::fs::File>, target: &mut std::fs::File) -> Result<(), std::io::Error> {
-    let mut remaining_adds_runs = &self.data[..];
-    let mut remaining_addresses = &self.addresses[..];
-    let mut target_data = Vec::with_capacity(self.target_window_length as usize);
-    let mut address_cache = AddressCache::new(4,3);
-    let window_header = &self;
-    if window_header.delta_indicator > 0 {
-      Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Unsupported compression type"))?;
-    }
-    {
-      let mut decode_instruction = | inst: Instruction, instructions: &[u8] | -> Result<usize, std::io::Error> {
-        let mut size = inst.size as usize;
-        let mut remaining_instructions = instructions;
-        if size == 0 {
-          let mut result : usize = 0;
-          let mut not_finished : bool = true;
-          let mut counter = 0;
-          while not_finished {
-            if counter == 10 || counter == (remaining_instructions.len()) {
-              return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "unable to get instruction address"));
-            }
-            let next_byte = remaining_instructions[counter];
-            counter += 1;
-            result = (result << 7) | (next_byte as usize & 127);
-            if (next_byte & 128) == 0 {
-              not_finished = false;
+  /**
+  * Executes the instructions of this window and appends the result to `target`.
+  * `index` is the position of the window in the patch and only used for error reporting.
+  */
+  pub fn decode_window<S: Source, T: Target>(self, index: u64, original: &mut Option<S>, target: &mut T, code_table: &CodeTable, options: &DecodeOptions) -> Result<(), XdeltaError> {
+    let source_length = self.source_segment.map_or(0u64, |r| r.0);
+    if let Some((segment_length, pos)) = self.source_segment {
+      let end = pos.checked_add(segment_length);
+      if self.window_indicator % 4 >= 2 && end.is_none_or(|end| end > target.position()) { //VCD_TARGET
+        return Err(XdeltaError::InvalidWindow("VCD_TARGET segment reaches past the decoded target"));
+      }
+    }
+    let target_end = target.position().saturating_add(self.target_window_length);
+    check_limit("max_target_size", options.limits.max_target_size, target_end)?;
+    let mut output = WindowOutput::new(target, self.target_window_length, options);
+
+    for operation in self.instructions(code_table) {
+      match operation? {
+        Operation::Add { data } => output.add(data)?,
+        Operation::Run { byte, size } => output.run(byte, size as usize)?,
+        Operation::Copy { address, size, .. } => match self.source_segment {
+          Some((segment_length, pos)) if address < segment_length => {
+            // like xdelta3, a copy from the segment can't continue into the target window
+            if size > segment_length - address {
+              return Err(XdeltaError::InvalidWindow("COPY crosses the end of the segment"));
             }
-          }
-					remaining_instructions = &remaining_instructions[counter..];
-          size = result;
-        }
-        match inst.typ {
-          InstructionType::Add => {
-            target_data.extend_from_slice(&remaining_adds_runs[0..size]);
-            remaining_adds_runs = &remaining_adds_runs[size..];
-          }
-          InstructionType::Copy => {
-            let source_length = window_header.source_segment.map_or(0u64, |r| r.0);
-            let (r, addr) = address_cache.decode((target_data.len() as u64) + source_length, inst.mode,remaining_addresses,)?;
-            remaining_addresses = &r;
-            let s = window_header.source_segment.and_then(|(sz, pos)| {
-              if addr < sz {
-                Some(pos)
-              } else {
-                None
-              }
-            });
-
-            if let Some(pos) = s {
-              let target_pos = target_data.len();
-              target_data.resize(target_pos + size, 0u8);
-              if window_header.window_indicator % 2 >= 1 { //VCD_SOURCE
-                original.as_mut().unwrap().seek(std::io::SeekFrom::Start(pos + addr))?;
-                original.as_mut().unwrap().read(&mut target_data[target_pos..target_pos + size])?;
-              } else {
-                let current = target.seek(std::io::SeekFrom::Current(0))?;
-                target.seek(std::io::SeekFrom::Start(pos + addr))?;
-                target.read(&mut target_data[target_pos..target_pos + size])?;
-                target.seek(std::io::SeekFrom::Start(current))?;
-              }
+            if self.window_indicator % 2 >= 1 { //VCD_SOURCE
+              let original = match original.as_mut() {
+                Some(original) => original,
+                None => return Err(XdeltaError::InvalidWindow("VCD_SOURCE window without a source file")),
+              };
+              output.copy_source(original, pos + address, size as usize)?;
             } else {
-              let target_pos = (addr - source_length) as usize;
-              for idx in target_pos..target_pos + size {
-                let byte = target_data[idx];
-                target_data.push(byte);
-              }
+              output.copy_target(pos + address, size as usize)?;
             }
-          }
-          InstructionType::Run => {
-            let byte = remaining_adds_runs[0];
-            let pos = target_data.len();
-            remaining_adds_runs = &remaining_adds_runs[1..];
-            target_data.resize(pos + size, byte);
-          }
-        };
-        Ok(instructions.len() - remaining_instructions.len())
+          },
+          _ => output.copy_window(address - source_length, size as usize)?,
+        },
+      }
+    }
+
+    if output.len() != self.target_window_length {
+      return Err(XdeltaError::InvalidWindow("instructions produce less than the target window length"));
+    }
+    let expected = self.adler32_checksum.map(u32::from_be_bytes);
+    output.finish(index, expected)
+  }
+
+  /// The instructions of this window, its sections have to be decompressed already
+  pub fn instructions<'a>(&'a self, code_table: &'a CodeTable) -> Instructions<'a> {
+    let source_length = self.source_segment.map_or(0u64, |r| r.0);
+    Instructions::new(code_table, source_length, self.target_window_length, &self.data, &self.instructions, &self.addresses)
+  }
+}
+
+/// VCD_SOURCE, VCD_TARGET and xdelta3's VCD_ADLER32 are known, the first two exclude each other
+pub fn check_window_indicator(window_indicator: u8) -> Result<(), XdeltaError> {
+  if window_indicator & !0x07 != 0 || window_indicator & 0x03 == 0x03 {
+    return Err(XdeltaError::ReservedBits { indicator: "window indicator", value: window_indicator });
+  }
+  Ok(())
+}
+
+/**
+* Collects the output of one window. Without `DecodeOptions::stream_chunk_size`, or if the target
+* doesn't stream windows, the whole window is written at the end. Otherwise every full chunk is
+* written right away and COPYs of earlier parts of the window read them back from the target.
+*/
+struct WindowOutput<'a, T: 'a> {
+  target: &'a mut T,
+  /// target position of the first byte of the window
+  start: u64,
+  /// bytes of the window that were written to the target already
+  flushed: u64,
+  pending: Vec<u8>,
+  chunk_size: usize,
+  checksum: Option<Adler32>,
+}
+
+impl<'a, T: Target> WindowOutput<'a, T> {
+  fn new(target: &'a mut T, window_length: u64, options: &DecodeOptions) -> WindowOutput<'a, T> {
+    let chunk_size = match options.stream_chunk_size {
+      Some(chunk_size) if target.streams_windows() => chunk_size.max(1),
+      _ => usize::MAX,
+    };
+    WindowOutput {
+      start: target.position(),
+      target,
+      flushed: 0,
+      pending: Vec::with_capacity((window_length as usize).min(chunk_size)),
+      chunk_size,
+      checksum: if options.verify_checksums { Some(Adler32::new()) } else { None },
+    }
+  }
+
+  /// Bytes of the window produced so far
+  fn len(&self) -> u64 {
+    self.flushed + self.pending.len() as u64
+  }
+
+  /// How many bytes may be appended to `pending`, writing it out first if it's full
+  fn room(&mut self) -> Result<usize, XdeltaError> {
+    if self.pending.len() >= self.chunk_size {
+      if let Some(ref mut checksum) = self.checksum {
+        checksum.update(&self.pending);
+      }
+      self.target.write_all(&self.pending)?;
+      self.flushed += self.pending.len() as u64;
+      self.pending.clear();
+    }
+    Ok(self.chunk_size - self.pending.len())
+  }
+
+  fn add(&mut self, mut bytes: &[u8]) -> Result<(), XdeltaError> {
+    while !bytes.is_empty() {
+      let n = self.room()?.min(bytes.len());
+      self.pending.extend_from_slice(&bytes[..n]);
+      bytes = &bytes[n..];
+    }
+    Ok(())
+  }
+
+  fn run(&mut self, byte: u8, mut size: usize) -> Result<(), XdeltaError> {
+    while size > 0 {
+      let n = self.room()?.min(size);
+      let len = self.pending.len();
+      self.pending.resize(len + n, byte);
+      size -= n;
+    }
+    Ok(())
+  }
+
+  fn copy_source<S: Source>(&mut self, source: &mut S, mut pos: u64, mut size: usize) -> Result<(), XdeltaError> {
+    while size > 0 {
+      let n = self.room()?.min(size);
+      let len = self.pending.len();
+      self.pending.resize(len + n, 0);
+      source.read_at(pos, &mut self.pending[len..])?;
+      pos += n as u64;
+      size -= n;
+    }
+    Ok(())
+  }
+
+  /// Copies from the output of earlier windows, `pos` is a target position
+  fn copy_target(&mut self, mut pos: u64, mut size: usize) -> Result<(), XdeltaError> {
+    while size > 0 {
+      let n = self.room()?.min(size);
+      let len = self.pending.len();
+      self.pending.resize(len + n, 0);
+      self.target.read_at(pos, &mut self.pending[len..])?;
+      pos += n as u64;
+      size -= n;
+    }
+    Ok(())
+  }
+
+  /// Copies from earlier in this window, `from` is relative to the window start.
+  /// Source and destination may overlap, which repeats the copied bytes.
+  fn copy_window(&mut self, mut from: u64, mut size: usize) -> Result<(), XdeltaError> {
+    if from >= self.len() && size > 0 {
+      return Err(XdeltaError::InvalidWindow("copy reaches past the end of the window output"));
+    }
+    while size > 0 {
+      let room = self.room()?;
+      let len = self.pending.len();
+      let n = if from >= self.flushed {
+        let offset = (from - self.flushed) as usize;
+        let n = room.min(size).min(len - offset);
+        self.pending.extend_from_within(offset..offset + n);
+        n
+      } else {
+        let n = room.min(size).min((self.flushed - from) as usize);
+        self.pending.resize(len + n, 0);
+        self.target.read_at(self.start + from, &mut self.pending[len..])?;
+        n
       };
-      let mut remaining_instructions = &self.instructions[..];
-      let code_table = CodeTable::default();
-      while let Some((&inst_index, r)) = remaining_instructions.split_first() {
-        let e = code_table.entries[inst_index as usize];
-        remaining_instructions = r;
-        remaining_instructions = &remaining_instructions[decode_instruction(e.0, remaining_instructions)?..];
-        if let Some(inst) = e.1 { //if e.1 exists unwrap it as the var "inst"
-          remaining_instructions = &remaining_instructions[decode_instruction(inst, remaining_instructions)?..];
+      from += n as u64;
+      size -= n;
+    }
+    Ok(())
+  }
+
+  /// Verifies the window checksum and writes what's left. A corrupt window leaves the target at its start.
+  fn finish(mut self, index: u64, expected: Option<u32>) -> Result<(), XdeltaError> {
+    if let (Some(mut checksum), Some(expected)) = (self.checksum.take(), expected) {
+      checksum.update(&self.pending);
+      let actual = checksum.checksum();
+      if expected != actual {
+        if self.flushed > 0 {
+          self.target.rewind(self.start)?;
         }
+        return Err(XdeltaError::ChecksumMismatch { window: index, expected, actual });
       }
     }
-    target.write(&target_data)?;
+    self.target.write_all(&self.pending)?;
+    self.target.end_window();
     Ok(())
   }
 }
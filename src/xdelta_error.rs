@@ -0,0 +1,88 @@
+use std::convert::From;
+use std::io::Error as IoError;
+
+use lzma_error::LzmaError;
+
+
+/// An error produced while decoding a VCDIFF/xdelta3 patch
+#[derive(Debug)]
+pub enum XdeltaError {
+  /// std::io::Error from the source, patch or target
+  Io(IoError),
+  /// The patch does not start with the VCDIFF magic bytes
+  InvalidMagic([u8; 4]),
+  /// The version byte after the magic is not 0, the only version defined by RFC 3284
+  UnsupportedVersion(u8),
+  /// An indicator byte has bits set that no known format extension uses
+  ReservedBits { indicator: &'static str, value: u8 },
+  /// The patch ended in the middle of a header or window
+  UnexpectedEof,
+  /// A variable-length integer was cut off, is longer than 10 bytes or doesn't fit in 64 bits
+  TruncatedVarint,
+  /// The header names a secondary compressor this crate can't decode
+  UnsupportedSecondaryCompressor(u8),
+  /// The application-defined code table (VCD_CODETABLE) can't be used
+  InvalidCodeTable(&'static str),
+  /// The sections of a window don't describe a valid delta
+  InvalidWindow(&'static str),
+  /// The VCD_ADLER32 checksum of a decoded target window doesn't match
+  ChecksumMismatch { window: u64, expected: u32, actual: u32 },
+  /// A DJW or FGK coded secondary section is corrupt
+  InvalidSecondaryData(&'static str),
+  /// Decompressing a secondary section failed
+  Lzma(LzmaError),
+  /// A checkpoint can't be read or doesn't belong to the patch and target it's resumed with
+  InvalidCheckpoint(&'static str),
+  /// A size read from the patch is above the `DecodeLimits` field named `limit`
+  LimitExceeded { limit: &'static str, value: u64 },
+  /// A `ProgressObserver` stopped the decoding after this many windows
+  Cancelled { windows: u64 },
+  /// `PatchInspector` was asked to decode after it yielded this many windows, which it didn't apply
+  WindowsAlreadyRead { windows: u64 },
+}
+
+impl std::fmt::Display for XdeltaError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match *self {
+      XdeltaError::Io(ref err) => write!(f, "IO error: {}", err),
+      XdeltaError::InvalidMagic(magic) => write!(f, "Not a VCDIFF patch, found magic bytes {:02X?}", magic),
+      XdeltaError::UnsupportedVersion(version) => write!(f, "Unsupported VCDIFF version {:#04x}", version),
+      XdeltaError::ReservedBits { indicator, value } => write!(f, "Reserved bits set in the {} {:#04x}", indicator, value),
+      XdeltaError::UnexpectedEof => write!(f, "Patch ended unexpectedly"),
+      XdeltaError::TruncatedVarint => write!(f, "Variable-length integer is truncated or too long"),
+      XdeltaError::UnsupportedSecondaryCompressor(id) => write!(f, "Unsupported secondary compressor id {}", id),
+      XdeltaError::InvalidCodeTable(details) => write!(f, "Invalid code table: {}", details),
+      XdeltaError::InvalidWindow(details) => write!(f, "Invalid window: {}", details),
+      XdeltaError::ChecksumMismatch { window, expected, actual } =>
+        write!(f, "Checksum mismatch in window {}, expected {:08x} but got {:08x}", window, expected, actual),
+      XdeltaError::InvalidSecondaryData(details) => write!(f, "Invalid secondary section: {}", details),
+      XdeltaError::Lzma(ref err) => write!(f, "LZMA error: {}", err),
+      XdeltaError::InvalidCheckpoint(details) => write!(f, "Invalid checkpoint: {}", details),
+      XdeltaError::LimitExceeded { limit, value } => write!(f, "Patch exceeds {}: {} bytes", limit, value),
+      XdeltaError::Cancelled { windows } => write!(f, "Decoding cancelled after {} windows", windows),
+      XdeltaError::WindowsAlreadyRead { windows } => write!(f, "Can't decode a patch of which {} windows were read already", windows),
+    }
+  }
+}
+
+impl std::error::Error for XdeltaError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match *self {
+      XdeltaError::Io(ref err) => Some(err),
+      XdeltaError::Lzma(ref err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<IoError> for XdeltaError {
+  fn from(err: IoError) -> XdeltaError {
+    XdeltaError::Io(err)
+  }
+}
+
+impl From<LzmaError> for XdeltaError {
+  fn from(err: LzmaError) -> XdeltaError {
+    XdeltaError::Lzma(err)
+  }
+}
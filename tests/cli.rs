@@ -0,0 +1,175 @@
+//! Runs the `xdelta` binary on the patches of tests/corpus and checks its output and exit codes.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// The source or target file all patches of tests/corpus were made from
+fn corpus_file(name: &str) -> PathBuf {
+  PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus").join(name)
+}
+
+fn patch_file(case: &str) -> PathBuf {
+  corpus_file(case).join("patch.vcdiff")
+}
+
+/// An empty directory of its own for each test, removed when it's dropped
+struct WorkDir(PathBuf);
+
+impl Drop for WorkDir {
+  fn drop(&mut self) {
+    let _ = fs::remove_dir_all(&self.0);
+  }
+}
+
+impl std::ops::Deref for WorkDir {
+  type Target = Path;
+
+  fn deref(&self) -> &Path {
+    &self.0
+  }
+}
+
+fn work_dir(test: &str) -> WorkDir {
+  let dir = std::env::temp_dir().join(format!("xdelta-cli-{}-{}", test, std::process::id()));
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  WorkDir(dir)
+}
+
+fn xdelta(args: &[&str], stdin: Option<&[u8]>) -> Output {
+  xdelta_in(&std::env::temp_dir(), args, stdin)
+}
+
+/// Runs the binary in `dir`, where it writes a target named in the patch
+fn xdelta_in(dir: &Path, args: &[&str], stdin: Option<&[u8]>) -> Output {
+  let mut child = Command::new(env!("CARGO_BIN_EXE_xdelta"))
+    .current_dir(dir)
+    .args(args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+  let mut input = child.stdin.take().unwrap();
+  if let Some(bytes) = stdin {
+    input.write_all(bytes).unwrap();
+  }
+  drop(input);
+  child.wait_with_output().unwrap()
+}
+
+fn path(path: &Path) -> &str {
+  path.to_str().unwrap()
+}
+
+#[test]
+fn decodes_to_a_file() {
+  let dir = work_dir("file");
+  let output = dir.join("out");
+  let result = xdelta(&["-d", "-s", path(&corpus_file("source")), path(&patch_file("default")), path(&output)], None);
+  assert_eq!(result.status.code(), Some(0), "{}", String::from_utf8_lossy(&result.stderr));
+  assert!(fs::read(&output).unwrap() == fs::read(corpus_file("target")).unwrap());
+}
+
+#[test]
+fn refuses_to_overwrite_without_force() {
+  let dir = work_dir("overwrite");
+  let output = dir.join("out");
+  fs::write(&output, b"keep me").unwrap();
+  let (source, patch) = (corpus_file("source"), patch_file("default"));
+  let args = ["-d", "-s", path(&source), path(&patch), path(&output)];
+  let result = xdelta(&args, None);
+  assert_eq!(result.status.code(), Some(1));
+  assert!(String::from_utf8_lossy(&result.stderr).contains("to overwrite output file specify -f"));
+  assert_eq!(fs::read(&output).unwrap(), b"keep me");
+
+  let result = xdelta(&[&["-f"], &args[..]].concat(), None);
+  assert_eq!(result.status.code(), Some(0));
+  assert!(fs::read(&output).unwrap() == fs::read(corpus_file("target")).unwrap());
+}
+
+#[test]
+fn writes_to_standard_output() {
+  let expected = fs::read(corpus_file("target")).unwrap();
+  let result = xdelta(&["-dc", "-s", path(&corpus_file("source")), path(&patch_file("default"))], None);
+  assert_eq!(result.status.code(), Some(0));
+  assert!(result.stdout == expected);
+
+  // the patch from standard input, with neither a patch nor a target file the target goes to standard output
+  let patch = fs::read(patch_file("default")).unwrap();
+  let result = xdelta(&["-d", "-s", path(&corpus_file("source"))], Some(&patch));
+  assert_eq!(result.status.code(), Some(0));
+  assert!(result.stdout == expected);
+
+  // windows of 8, 20 and 20 bytes, the last two copy from the output of the one before
+  let patch = [
+    0xD6, 0xC3, 0xC4, 0x00, 0x00,
+    0x00, 0x0E, 0x08, 0x00, 0x08, 0x01, 0x00, b'a', b'b', b'c', b'd', b'e', b'f', b'g', b'h', 0x09,
+    0x02, 0x08, 0x00, 0x0C, 0x14, 0x00, 0x02, 0x03, 0x02, b'X', b'Y', 0x18, 0x03, 0x1A, 0x00, 0x08,
+    0x02, 0x14, 0x08, 0x08, 0x14, 0x00, 0x00, 0x02, 0x01, 0x13, 0x14, 0x00,
+  ];
+  let result = xdelta(&["-dc"], Some(&patch));
+  assert_eq!(result.status.code(), Some(0));
+  assert_eq!(result.stdout, &b"abcdefghabcdefghXYabcdefghXYabcdefghXYabcdefghXY"[..]);
+}
+
+/// The patch is read once, the target name comes from its header
+#[cfg(unix)]
+#[test]
+fn names_the_target_from_a_pipe() {
+  let dir = work_dir("pipe");
+  let patch = fs::read(patch_file("appheader")).unwrap();
+  let result = xdelta_in(&dir, &["-d", "-s", path(&corpus_file("source")), "/dev/stdin"], Some(&patch));
+  assert_eq!(result.status.code(), Some(0), "{}", String::from_utf8_lossy(&result.stderr));
+  assert!(fs::read(dir.join("target.txt")).unwrap() == fs::read(corpus_file("target")).unwrap());
+}
+
+/// Without `-s` the source is the file named in the patch, like the target
+#[test]
+fn opens_the_recorded_source() {
+  let dir = work_dir("recorded-source");
+  fs::copy(patch_file("appheader"), dir.join("patch.vcdiff")).unwrap();
+  let result = xdelta_in(&dir, &["-d", "patch.vcdiff"], None);
+  assert_eq!(result.status.code(), Some(1));
+  assert!(String::from_utf8_lossy(&result.stderr).contains("source.txt"));
+
+  fs::copy(corpus_file("source"), dir.join("source.txt")).unwrap();
+  let result = xdelta_in(&dir, &["-d", "patch.vcdiff"], None);
+  assert_eq!(result.status.code(), Some(0), "{}", String::from_utf8_lossy(&result.stderr));
+  assert!(fs::read(dir.join("target.txt")).unwrap() == fs::read(corpus_file("target")).unwrap());
+}
+
+#[test]
+fn fails_on_bad_input() {
+  let dir = work_dir("bad-input");
+  let mut patch = fs::read(patch_file("default")).unwrap();
+  let last = patch.len() - 1;
+  patch[last] ^= 0xFF;
+  fs::write(dir.join("patch.vcdiff"), &patch).unwrap();
+  let source = corpus_file("source");
+  let result = xdelta(&["-dc", "-s", path(&source), path(&dir.join("patch.vcdiff"))], None);
+  assert_eq!(result.status.code(), Some(1));
+  assert!(!result.stderr.is_empty());
+
+  let result = xdelta(&["-dcq", "-s", path(&source), path(&dir.join("patch.vcdiff"))], None);
+  assert_eq!(result.status.code(), Some(1));
+  assert!(result.stderr.is_empty());
+
+  // neither the target nor the file it was decoded into are left behind, an existing target is kept
+  let output = dir.join("out");
+  let result = xdelta(&["-d", "-s", path(&source), path(&dir.join("patch.vcdiff")), path(&output)], None);
+  assert_eq!(result.status.code(), Some(1));
+  assert_eq!(fs::read_dir(&*dir).unwrap().count(), 1);
+  fs::write(&output, b"keep me").unwrap();
+  let result = xdelta(&["-df", "-s", path(&source), path(&dir.join("patch.vcdiff")), path(&output)], None);
+  assert_eq!(result.status.code(), Some(1));
+  assert_eq!(fs::read(&output).unwrap(), b"keep me");
+  assert_eq!(fs::read_dir(&*dir).unwrap().count(), 2);
+
+  let result = xdelta(&["-dc", "-s", path(&dir.join("missing")), path(&patch_file("default"))], None);
+  assert_eq!(result.status.code(), Some(1));
+  let result = xdelta(&["-e", "-s", path(&source)], None);
+  assert_eq!(result.status.code(), Some(1));
+}
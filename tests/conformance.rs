@@ -0,0 +1,192 @@
+//! Decodes every patch of tests/corpus through the public API and compares the result with the
+//! expected target. The patches were made by xdelta3 3.0.12 with the command lines noted at each
+//! test, see tests/corpus/README.md.
+
+extern crate xdelta;
+#[cfg(feature = "async")]
+extern crate tokio;
+
+use xdelta::{decode_file, decode_to_vec, decode_to_writer, DecodeOptions, PatchInspector};
+
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+/// Newlines in front of the source and target of `source-window`, so that its source is larger
+/// than the smallest source window xdelta3 allows
+const PADDING: usize = 540_000;
+
+fn corpus_dir() -> PathBuf {
+  PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus")
+}
+
+fn case_dir(case: &str) -> PathBuf {
+  corpus_dir().join(case)
+}
+
+/// The source or target file all cases were made from
+fn corpus_file(name: &str) -> Vec<u8> {
+  fs::read(corpus_dir().join(name)).unwrap()
+}
+
+fn padded(bytes: Vec<u8>) -> Vec<u8> {
+  let mut padded = vec![b'\n'; PADDING];
+  padded.extend_from_slice(&bytes);
+  padded
+}
+
+/// Decodes the patch of `case` in memory, to a file and to a plain writer, and with each optional API
+fn check(case: &str, source: Option<&[u8]>, expected: &[u8]) {
+  let patch_path = case_dir(case).join("patch.vcdiff");
+  let patch = fs::read(&patch_path).unwrap();
+
+  let output = decode_to_vec(source.unwrap_or(&[]), &patch).unwrap();
+  assert!(output == expected, "{}: decode_to_vec differs from the expected target", case);
+
+  let dir = std::env::temp_dir().join(format!("xdelta-conformance-{}-{}", case, std::process::id()));
+  fs::create_dir_all(&dir).unwrap();
+  let source_path = source.map(|source| {
+    fs::write(dir.join("source"), source).unwrap();
+    dir.join("source")
+  });
+  decode_file(source_path.clone(), patch_path, dir.join("target")).unwrap();
+  assert!(fs::read(dir.join("target")).unwrap() == expected, "{}: decode_file differs from the expected target", case);
+
+  let mut output = Vec::new();
+  let source_file = source_path.map(|path| File::open(path).unwrap());
+  decode_to_writer(source_file, &patch[..], &mut output, &DecodeOptions::default()).unwrap();
+  assert!(output == expected, "{}: decode_to_writer differs from the expected target", case);
+  fs::remove_dir_all(dir).unwrap();
+
+  #[cfg(feature = "parallel")]
+  {
+    let mut output = std::io::Cursor::new(Vec::new());
+    xdelta::decode_parallel(source.unwrap_or(&[]), &patch[..], &mut output, &DecodeOptions::default()).unwrap();
+    assert!(output.into_inner() == expected, "{}: decode_parallel differs from the expected target", case);
+  }
+
+  #[cfg(feature = "async")]
+  {
+    let mut output = Vec::new();
+    let source = source.map(std::io::Cursor::new);
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    runtime.block_on(xdelta::decode_async(source, &patch[..], &mut output)).unwrap();
+    assert!(output == expected, "{}: decode_async differs from the expected target", case);
+  }
+}
+
+/// Checks a case made from the shared source and target
+fn check_shared(case: &str) {
+  check(case, Some(&corpus_file("source")), &corpus_file("target"));
+}
+
+fn inspect(case: &str) -> PatchInspector<File> {
+  PatchInspector::new(File::open(case_dir(case).join("patch.vcdiff")).unwrap()).unwrap()
+}
+
+/// Checksums and whether the patch has them, per window
+fn checksums(case: &str) -> Vec<Option<u32>> {
+  inspect(case).map(|window| window.unwrap().adler32_checksum).collect()
+}
+
+/// `xdelta3 -e -S none -s source target patch.vcdiff`
+#[test]
+fn default_flags() {
+  check_shared("default");
+  assert!(checksums("default").iter().all(Option::is_some));
+  let app_header = inspect("default").header().app_header().unwrap();
+  assert_eq!(app_header.target_name.as_deref(), Some("target"));
+  assert_eq!(app_header.source_name.as_deref(), Some("source"));
+}
+
+/// `xdelta3 -e -n -S none -s source target patch.vcdiff`
+#[test]
+fn no_checksum() {
+  check_shared("no-checksum");
+  assert!(checksums("no-checksum").iter().all(Option::is_none));
+}
+
+/// `xdelta3 -e -S djw -s source target patch.vcdiff`
+#[test]
+fn secondary_djw() {
+  check_shared("secondary-djw");
+}
+
+/// `xdelta3 -e -S djw9 -s source target patch.vcdiff`, which codes every section with several groups
+#[test]
+fn secondary_djw_groups() {
+  check_shared("secondary-djw-groups");
+}
+
+/// `xdelta3 -e -S fgk -s source target patch.vcdiff`
+#[test]
+fn secondary_fgk() {
+  check_shared("secondary-fgk");
+}
+
+/// `xdelta3 -e -S lzma -W 16384 -s source target patch.vcdiff`
+#[cfg(any(feature = "lzma-sys", feature = "pure-rust-lzma"))]
+#[test]
+fn secondary_lzma() {
+  check_shared("secondary-lzma");
+  assert!(checksums("secondary-lzma").len() > 1);
+}
+
+/// `xdelta3 -e -S none -A=target.txt//source.txt/ -s source target patch.vcdiff`
+#[test]
+fn custom_app_header() {
+  check_shared("appheader");
+  let app_header = inspect("appheader").header().app_header().unwrap();
+  assert_eq!(app_header.target_name.as_deref(), Some("target.txt"));
+  assert_eq!(app_header.source_name.as_deref(), Some("source.txt"));
+}
+
+/// `xdelta3 -e -S none -A= -s source target patch.vcdiff`
+#[test]
+fn no_app_header() {
+  check_shared("no-appheader");
+  assert!(inspect("no-appheader").header().app_header().is_none());
+}
+
+/// `xdelta3 -e -S none -W 16384 -s source target patch.vcdiff`
+#[test]
+fn small_target_windows() {
+  check_shared("small-windows");
+  assert!(checksums("small-windows").len() > 1);
+}
+
+/// `xdelta3 -e -S none -B 524288 -W 65536 -s source target patch.vcdiff`, with the source and
+/// target behind `PADDING` newlines
+#[test]
+fn source_segments() {
+  check("source-window", Some(&padded(corpus_file("source"))), &padded(corpus_file("target")));
+  let segments : Vec<(u64, u64)> = inspect("source-window").filter_map(|window| window.unwrap().source_segment).collect();
+  assert!(segments.iter().any(|&(_, position)| position > 0));
+}
+
+/// `xdelta3 -e -S none -W 16384 target patch.vcdiff`. Its windows only copy from themselves, so
+/// `decode_to_writer` needs no history of earlier windows.
+#[test]
+fn target_windows() {
+  check("target-windows", None, &corpus_file("target"));
+  let windows : Vec<_> = inspect("target-windows").map(Result::unwrap).collect();
+  assert!(windows.len() > 1 && windows.iter().all(|window| !window.is_source() && !window.is_target()));
+
+  let patch = fs::read(case_dir("target-windows").join("patch.vcdiff")).unwrap();
+  let options = DecodeOptions { target_history: Some(0), ..DecodeOptions::default() };
+  let mut output = Vec::new();
+  decode_to_writer(None::<File>, &patch[..], &mut output, &options).unwrap();
+  assert!(output == corpus_file("target"));
+}
+
+/// `xdelta3 -e -S none target patch.vcdiff`
+#[test]
+fn empty_source() {
+  check("empty-source", None, &corpus_file("target"));
+}
+
+/// `xdelta3 -e -S none -s source target patch.vcdiff` with an empty target
+#[test]
+fn empty_target() {
+  check("empty-target", Some(&corpus_file("source")), &[]);
+  assert_eq!(inspect("empty-target").map(|window| window.unwrap().target_window_length).collect::<Vec<_>>(), [0]);
+}